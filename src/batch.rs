@@ -0,0 +1,271 @@
+//! This module offers a JSON-RPC transport which combines concurrent requests into batches, e.g.
+//! to speed up many small reads of encrypted balances.
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use ethers::providers::{JsonRpcClient, JsonRpcError, ProviderError, RpcError};
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::oneshot;
+
+/// Controls how [`BatchHttp`] groups requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// How long to wait for more requests after the first request of a batch arrives.
+    pub window: Duration,
+    /// Send a batch as soon as it holds this many requests, without waiting for the window to
+    /// elapse.
+    pub max_size: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(10),
+            max_size: 100,
+        }
+    }
+}
+
+/// An error from a [`BatchHttp`] request.
+#[derive(Debug, thiserror::Error)]
+pub enum BatchHttpError {
+    /// The node responded to this request with an error.
+    #[error(transparent)]
+    JsonRpc(#[from] JsonRpcError),
+    /// The batch containing this request could not be sent, or its response was malformed.
+    #[error("Batch request failed: {0}")]
+    Transport(String),
+    /// This request's result could not be deserialized.
+    #[error("Deserialization error: {err}: {text}")]
+    Serde {
+        err: serde_json::Error,
+        text: String,
+    },
+}
+
+impl RpcError for BatchHttpError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            BatchHttpError::JsonRpc(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            BatchHttpError::Serde { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<BatchHttpError> for ProviderError {
+    fn from(e: BatchHttpError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(e))
+    }
+}
+
+/// An HTTP transport which transparently combines concurrent requests into JSON-RPC batch
+/// requests. Each caller still receives the response to its own request.
+///
+/// Use it via [`crate::testnet::TestnetProvider::batched_provider`] or
+/// [`crate::testing::Node::batched_provider`].
+#[derive(Debug, Clone)]
+pub struct BatchHttp {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    url: Url,
+    client: reqwest::Client,
+    options: BatchOptions,
+    next_id: AtomicU64,
+    queue: Mutex<Vec<Queued>>,
+}
+
+/// A request waiting to be sent in the next batch.
+#[derive(Debug)]
+struct Queued {
+    id: u64,
+    request: Value,
+    respond: oneshot::Sender<Result<Value, BatchHttpError>>,
+}
+
+/// One response within a batch response.
+#[derive(Deserialize)]
+struct Response {
+    id: u64,
+    #[serde(default)]
+    result: Value,
+    error: Option<JsonRpcError>,
+}
+
+impl BatchHttp {
+    /// Construct a transport sending batches to `url` with `client`.
+    pub fn new(url: Url, client: reqwest::Client, options: BatchOptions) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                url,
+                client,
+                options,
+                next_id: AtomicU64::new(1),
+                queue: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+}
+
+impl Inner {
+    /// Take the queued requests, if any, and send them as one batch.
+    async fn flush(self: Arc<Self>) {
+        let batch = std::mem::take(&mut *self.queue.lock().unwrap_or_else(|e| e.into_inner()));
+        if batch.is_empty() {
+            return;
+        }
+        let requests = batch.iter().map(|q| &q.request).collect::<Vec<_>>();
+        match self.send(&requests).await {
+            Ok(responses) => {
+                let mut responses = responses
+                    .into_iter()
+                    .map(|response| (response.id, response))
+                    .collect::<HashMap<_, _>>();
+                for queued in batch {
+                    let result = match responses.remove(&queued.id) {
+                        Some(Response {
+                            error: Some(error), ..
+                        }) => Err(error.into()),
+                        Some(response) => Ok(response.result),
+                        None => Err(BatchHttpError::Transport(format!(
+                            "no response to request {}",
+                            queued.id
+                        ))),
+                    };
+                    // The caller may have given up on the request.
+                    let _ = queued.respond.send(result);
+                }
+            }
+            Err(e) => {
+                let message = e.to_string();
+                for queued in batch {
+                    let _ = queued
+                        .respond
+                        .send(Err(BatchHttpError::Transport(message.clone())));
+                }
+            }
+        }
+    }
+
+    async fn send(&self, requests: &[&Value]) -> reqwest::Result<Vec<Response>> {
+        self.client
+            .post(self.url.clone())
+            .json(requests)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl JsonRpcClient for BatchHttp {
+    type Error = BatchHttpError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, BatchHttpError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        let (respond, response) = oneshot::channel();
+
+        let queued = {
+            let mut queue = self.inner.queue.lock().unwrap_or_else(|e| e.into_inner());
+            queue.push(Queued {
+                id,
+                request,
+                respond,
+            });
+            queue.len()
+        };
+        // Batches are sent from their own tasks, so that they complete even if the caller that
+        // triggered them is dropped.
+        if queued >= self.inner.options.max_size {
+            tokio::spawn(self.inner.clone().flush());
+        } else if queued == 1 {
+            let inner = self.inner.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(inner.options.window).await;
+                inner.flush().await;
+            });
+        }
+
+        let value = response
+            .await
+            .map_err(|_| BatchHttpError::Transport("the batch was dropped".to_owned()))??;
+        serde_json::from_value(value.clone()).map_err(|err| BatchHttpError::Serde {
+            err,
+            text: value.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::{Middleware, Provider},
+        signers::Signer,
+        types::U256,
+    };
+
+    use super::*;
+    use crate::testing::{Node, ALICE, BOB};
+
+    #[tokio::test]
+    async fn batches_concurrent_requests() {
+        let node = Node::default();
+        let plain = node.provider();
+        let batched = node.batched_provider(BatchOptions {
+            window: Duration::from_millis(50),
+            max_size: 3,
+        });
+
+        let (alice, bob, block, unknown) = tokio::join!(
+            batched.get_balance(ALICE.address(), None),
+            batched.get_balance(BOB.address(), None),
+            batched.get_block_number(),
+            batched.request::<_, U256>("sunscreen_unknownMethod", ()),
+        );
+        assert_eq!(
+            alice.unwrap(),
+            plain.get_balance(ALICE.address(), None).await.unwrap()
+        );
+        assert_eq!(
+            bob.unwrap(),
+            plain.get_balance(BOB.address(), None).await.unwrap()
+        );
+        assert_eq!(block.unwrap(), plain.get_block_number().await.unwrap());
+        assert!(unknown.unwrap_err().as_error_response().is_some());
+
+        // A lone request is sent once the window elapses.
+        let provider: &Provider<BatchHttp> = &batched;
+        assert!(provider.get_chainid().await.is_ok());
+    }
+}
@@ -0,0 +1,243 @@
+//! This module offers a small dotenv-style configuration format for bootstrapping a development
+//! environment, e.g.
+//!
+//! ```text
+//! # Local anvil node
+//! RPC_URL=http://localhost:8545
+//! CHAIN_ID=31337
+//! WALLET_KEY=0x1c0eb5244c165957525ef389fc14fac4424feaaefabf87c7e4e15bcc7b425e15
+//! FHE_PUBLIC_KEY=keys/public.key
+//! FHE_PRIVATE_KEY=keys/private.key
+//! ```
+//!
+//! Each line is a `KEY=value` pair, optionally prefixed with `export` and with the value in single
+//! or double quotes. Blank lines and lines starting with `#` are ignored, as are unknown keys.
+//!
+//! `RPC_URL`, `CHAIN_ID`, `WALLET_KEY` (a hex encoded secp256k1 private key) and `FHE_PUBLIC_KEY`
+//! are required. `FHE_PRIVATE_KEY` is optional, e.g. for applications that only encrypt. Relative
+//! key paths are resolved against the directory containing the config file.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use ethers::{
+    providers::{Http, Provider},
+    signers::{LocalWallet, Signer},
+};
+
+use super::{
+    http_provider, AsFile, Error, FheRuntime, PrivateKey, PublicKey, Result, SignedMiddleware,
+    DEFAULT_REQUEST_TIMEOUT,
+};
+
+/// Settings loaded by [`load_config`].
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub rpc_url: String,
+    pub chain_id: u64,
+    /// The wallet to sign transactions with, already configured for [`Self::chain_id`].
+    pub wallet: LocalWallet,
+    pub public_key_path: PathBuf,
+    pub private_key_path: Option<PathBuf>,
+}
+
+/// Read settings from a dotenv-style file. See the [module documentation](self) for the format.
+///
+/// Returns [`Error::Config`] naming the offending field if a required field is missing or a field
+/// cannot be parsed. The key files are not read until they are needed.
+pub fn load_config<P: AsRef<Path>>(path: P) -> Result<AppConfig> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)?;
+    let mut fields = parse_fields(&contents)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let rpc_url = required(&mut fields, "RPC_URL")?;
+    reqwest::Url::parse(&rpc_url).map_err(|e| invalid("RPC_URL", e))?;
+    let chain_id = required(&mut fields, "CHAIN_ID")?
+        .parse()
+        .map_err(|e| invalid("CHAIN_ID", e))?;
+    let wallet = LocalWallet::from_str(&required(&mut fields, "WALLET_KEY")?)
+        .map_err(|e| invalid("WALLET_KEY", e))?
+        .with_chain_id(chain_id);
+    let public_key_path = dir.join(required(&mut fields, "FHE_PUBLIC_KEY")?);
+    let private_key_path = fields.remove("FHE_PRIVATE_KEY").map(|p| dir.join(p));
+
+    Ok(AppConfig {
+        rpc_url,
+        chain_id,
+        wallet,
+        public_key_path,
+        private_key_path,
+    })
+}
+
+impl AppConfig {
+    /// Construct a [`Provider<Http>`] for the configured RPC URL.
+    pub fn provider(&self) -> Provider<Http> {
+        http_provider(&self.rpc_url, DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    /// Construct a client which signs transactions with the configured wallet.
+    pub fn client(&self) -> SignedMiddleware {
+        SignedMiddleware::new(Arc::new(self.provider()), self.wallet.clone())
+    }
+
+    /// Read the configured FHE public key.
+    pub fn public_key(&self) -> Result<PublicKey> {
+        PublicKey::read(&self.public_key_path)
+    }
+
+    /// Read the configured FHE private key. Returns [`Error::Config`] if none is configured.
+    pub fn private_key(&self) -> Result<PrivateKey> {
+        match &self.private_key_path {
+            Some(path) => PrivateKey::read(path),
+            None => Err(missing("FHE_PRIVATE_KEY")),
+        }
+    }
+
+    /// Construct an [`FheRuntime`] for the parameters of the configured FHE public key.
+    pub fn runtime(&self) -> Result<FheRuntime> {
+        let public_key = self.public_key()?;
+        Ok(FheRuntime::new(&public_key.public_key.params)?)
+    }
+}
+
+/// Parse `KEY=value` lines into a map. Later lines override earlier ones.
+fn parse_fields(contents: &str) -> Result<HashMap<String, String>> {
+    let mut fields = HashMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("line {} is not a KEY=value pair", number + 1))?;
+        fields.insert(key.trim().to_owned(), unquote(value.trim()).to_owned());
+    }
+    Ok(fields)
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+fn required(fields: &mut HashMap<String, String>, field: &str) -> Result<String> {
+    match fields.remove(field) {
+        Some(value) if !value.is_empty() => Ok(value),
+        _ => Err(missing(field)),
+    }
+}
+
+fn missing(field: &str) -> Error {
+    Error::Config {
+        field: field.to_owned(),
+        reason: "missing".to_owned(),
+    }
+}
+
+fn invalid(field: &str, e: impl std::fmt::Display) -> Error {
+    Error::Config {
+        field: field.to_owned(),
+        reason: e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::runtime, Unsigned256};
+
+    /// The private key of [`crate::testing::ALICE`].
+    const WALLET_KEY: &str = "1c0eb5244c165957525ef389fc14fac4424feaaefabf87c7e4e15bcc7b425e15";
+
+    #[test]
+    fn loads_config_and_keys() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        public_key.write(dir.path().join("public.key")).unwrap();
+        private_key.write(dir.path().join("private.key")).unwrap();
+        let path = dir.path().join(".env");
+        std::fs::write(
+            &path,
+            format!(
+                "# dev settings\n\
+                 RPC_URL=\"http://localhost:8545\"\n\
+                 export CHAIN_ID=31337\n\n\
+                 WALLET_KEY='{}'\n\
+                 FHE_PUBLIC_KEY=public.key\n\
+                 FHE_PRIVATE_KEY=private.key\n",
+                WALLET_KEY
+            ),
+        )
+        .unwrap();
+
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.rpc_url, "http://localhost:8545");
+        assert_eq!(config.chain_id, 31337);
+        assert_eq!(config.wallet.chain_id(), 31337);
+        assert_eq!(config.wallet.address(), crate::testing::ALICE.address());
+        assert!(config.public_key().unwrap() == public_key);
+
+        let runtime = config.runtime().unwrap();
+        let ciphertext = runtime
+            .encrypt(Unsigned256::from(4), &config.public_key().unwrap())
+            .unwrap();
+        let value: Unsigned256 = runtime
+            .decrypt(&ciphertext, &config.private_key().unwrap())
+            .unwrap();
+        assert_eq!(value, Unsigned256::from(4));
+    }
+
+    #[test]
+    fn reports_missing_and_invalid_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        let check = |contents: String, expected: &str| {
+            std::fs::write(&path, contents).unwrap();
+            match load_config(&path) {
+                Err(Error::Config { field, .. }) => assert_eq!(field, expected),
+                _ => panic!("expected an error for {}", expected),
+            }
+        };
+
+        check("CHAIN_ID=1\n".to_owned(), "RPC_URL");
+        check("RPC_URL=http://localhost:8545\n".to_owned(), "CHAIN_ID");
+        check(
+            "RPC_URL=http://localhost:8545\nCHAIN_ID=one\n".to_owned(),
+            "CHAIN_ID",
+        );
+        check(
+            format!(
+                "RPC_URL=http://localhost:8545\nCHAIN_ID=1\nWALLET_KEY={}\n",
+                WALLET_KEY
+            ),
+            "FHE_PUBLIC_KEY",
+        );
+
+        std::fs::write(
+            &path,
+            format!(
+                "RPC_URL=http://localhost:8545\nCHAIN_ID=1\nWALLET_KEY={}\nFHE_PUBLIC_KEY=pk\n",
+                WALLET_KEY
+            ),
+        )
+        .unwrap();
+        let config = load_config(&path).unwrap();
+        assert!(matches!(
+            config.private_key(),
+            Err(Error::Config { field, .. }) if field == "FHE_PRIVATE_KEY"
+        ));
+    }
+}
@@ -0,0 +1,827 @@
+//! This module offers helpers for passing FHE values to and from contracts.
+
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::Hash,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
+};
+
+use ethers::{
+    abi::{
+        Abi, AbiArrayType, AbiDecode, AbiParser, AbiType, InvalidOutputType, ParamType, RawLog,
+        Token, Tokenizable, TokenizableItem, Tokenize,
+    },
+    contract::{Contract, ContractCall, EthLogDecode, Event, LogMeta},
+    providers::Middleware,
+    types::{
+        transaction::eip2718::TypedTransaction, Address, BlockNumber, Bytes, Log,
+        TransactionRequest, U256,
+    },
+    utils::keccak256,
+};
+
+use super::{
+    fhe, tx::middleware_error, AsBytes, Ciphertext, Error, FheRuntime, PrivateKey, PublicKey,
+    RedactedPrivateKey, Result,
+};
+
+/// Encode an FHE value as a `bytes` constructor argument, e.g. to deploy a contract initialized
+/// with an encrypted starting balance via [`ethers::contract::ContractFactory::deploy`].
+pub fn constructor_arg<T: AsBytes>(value: &T) -> Result<Token> {
+    Ok(Token::Bytes(value.as_bytes()?.to_vec()))
+}
+
+/// An FHE value encoded as an ABI `bytes` value. This lets encrypted fields appear directly in the
+/// types ethers decodes contract data into, e.g. a call returning `(uint256 id, bytes value)` can
+/// be decoded as `(U256, Encrypted<Ciphertext>)`, and so can a struct deriving `EthAbiType` with an
+/// `Encrypted<Ciphertext>` field. See [`decode_abi`].
+///
+/// Decoding fails if the token is not `bytes`, or if the bytes are not a serialized `T`.
+///
+/// # Panics
+/// Encoding panics if the value cannot be serialized, which does not happen for well-formed
+/// sunscreen values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encrypted<T>(pub T);
+
+impl<T> Deref for Encrypted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Encrypted<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: AsBytes> Tokenizable for Encrypted<T> {
+    fn from_token(token: Token) -> std::result::Result<Self, InvalidOutputType> {
+        match token {
+            Token::Bytes(bytes) => T::from_bytes(&bytes.into())
+                .map(Encrypted)
+                .map_err(|e| InvalidOutputType(format!("Invalid FHE value: {}", e))),
+            other => Err(InvalidOutputType(format!(
+                "Expected `bytes` for an FHE value, got {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn into_token(self) -> Token {
+        let bytes = self
+            .0
+            .as_bytes()
+            .expect("sunscreen values serialize infallibly");
+        Token::Bytes(bytes.to_vec())
+    }
+}
+
+impl<T: AsBytes> TokenizableItem for Encrypted<T> {}
+
+impl<T> AbiType for Encrypted<T> {
+    fn param_type() -> ParamType {
+        ParamType::Bytes
+    }
+}
+
+impl<T> AbiArrayType for Encrypted<T> {}
+
+/// Decode ABI-encoded data, e.g. a raw contract return value, into a type which may contain
+/// [`Encrypted`] fields.
+pub fn decode_abi<T: AbiDecode>(data: impl AsRef<[u8]>) -> Result<T> {
+    Ok(T::decode(data).map_err(anyhow::Error::new)?)
+}
+
+/// An argument to [`encode_call`], which is either a plain ABI token or an FHE value to be passed
+/// as `bytes`.
+#[derive(Clone)]
+pub enum FheArg<'a> {
+    Token(Token),
+    Ciphertext(&'a Ciphertext),
+    PublicKey(&'a PublicKey),
+}
+
+impl<'a> From<Token> for FheArg<'a> {
+    fn from(token: Token) -> Self {
+        FheArg::Token(token)
+    }
+}
+
+impl<'a> From<&'a Ciphertext> for FheArg<'a> {
+    fn from(ciphertext: &'a Ciphertext) -> Self {
+        FheArg::Ciphertext(ciphertext)
+    }
+}
+
+impl<'a> From<&'a PublicKey> for FheArg<'a> {
+    fn from(public_key: &'a PublicKey) -> Self {
+        FheArg::PublicKey(public_key)
+    }
+}
+
+impl<'a> FheArg<'a> {
+    fn into_token(self) -> Result<Token> {
+        match self {
+            FheArg::Token(token) => Ok(token),
+            FheArg::Ciphertext(ciphertext) => constructor_arg(ciphertext),
+            FheArg::PublicKey(public_key) => constructor_arg(public_key),
+        }
+    }
+}
+
+/// Encode the calldata for calling a function with a mix of plain and FHE arguments, e.g. to make
+/// raw calls to a contract without generated bindings.
+///
+/// `signature` is a human readable function signature, e.g. `"transfer(address,bytes)"` or
+/// `"function transfer(address to, bytes amount)"`. Returns an error if the arguments do not match
+/// the signature's parameter types.
+pub fn encode_call(signature: &str, args: &[FheArg]) -> Result<Bytes> {
+    let function = AbiParser::default()
+        .parse_function(signature)
+        .map_err(anyhow::Error::new)?;
+    let tokens = args
+        .iter()
+        .cloned()
+        .map(FheArg::into_token)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(function.encode_input(&tokens)?.into())
+}
+
+/// Check that a public key stored on-chain matches a local copy, e.g. before encrypting to it.
+///
+/// `call` should be a contract read returning the stored key's [`Bytes`]. Returns `false` if the
+/// stored key differs from `local`, and an error if the stored bytes are not a public key at all.
+pub async fn verify_onchain_public_key<M: Middleware + 'static>(
+    call: ContractCall<M, Bytes>,
+    local: &PublicKey,
+) -> Result<bool> {
+    let bytes = call.call().await.map_err(anyhow::Error::new)?;
+    let onchain = PublicKey::from_bytes(&bytes)?;
+    Ok(onchain == *local)
+}
+
+/// Read and decrypt an encrypted value from a contract, e.g. an entry of a
+/// `mapping(address => bytes)` of encrypted balances.
+///
+/// `call` should be a contract read returning the value's [`Bytes`]. Returns `None` if the value
+/// was never set, i.e. the contract returned empty bytes, which distinguishes it from an
+/// encrypted zero.
+pub async fn get_encrypted<M: Middleware + 'static>(
+    call: ContractCall<M, Bytes>,
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+) -> Result<Option<U256>> {
+    let bytes = call.call().await.map_err(anyhow::Error::new)?;
+    decrypt_if_set(runtime, private_key, &bytes)
+}
+
+/// Read an encrypted value from a contract like [`get_encrypted`], returning both the deserialized
+/// ciphertext and its decryption, e.g. for debug tooling that displays them side by side.
+///
+/// Unlike [`get_encrypted`], an unset value is an error, as there is no ciphertext to return.
+pub async fn read_both<M: Middleware + 'static>(
+    call: ContractCall<M, Bytes>,
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+) -> Result<(Ciphertext, U256)> {
+    let bytes = call.call().await.map_err(anyhow::Error::new)?;
+    fhe::decrypt_keeping_ciphertext(runtime, private_key, &bytes)
+}
+
+/// A contract handle which decrypts the encrypted values it reads with a runtime and private key.
+///
+/// All of [`Contract`]'s methods are available through [`Deref`], so calls that do not involve
+/// encrypted values work exactly as they would on the underlying contract. The private key is held
+/// as a [`RedactedPrivateKey`], so it is never formatted.
+pub struct FheContract<M> {
+    contract: Contract<M>,
+    runtime: Arc<FheRuntime>,
+    private_key: RedactedPrivateKey,
+}
+
+impl<M: Middleware + 'static> FheContract<M> {
+    /// Wrap a contract, decrypting with `runtime` and `private_key`.
+    pub fn new(
+        contract: Contract<M>,
+        runtime: Arc<FheRuntime>,
+        private_key: impl Into<RedactedPrivateKey>,
+    ) -> Self {
+        Self {
+            contract,
+            runtime,
+            private_key: private_key.into(),
+        }
+    }
+
+    /// Call a view method returning an encrypted value as `bytes` and decrypt the result. See
+    /// [`get_encrypted`].
+    pub async fn call_decrypted<T: Tokenize>(&self, name: &str, args: T) -> Result<Option<U256>> {
+        let call = self
+            .contract
+            .method::<_, Bytes>(name, args)
+            .map_err(anyhow::Error::new)?;
+        get_encrypted(call, &self.runtime, &self.private_key).await
+    }
+
+    /// The runtime values are decrypted with.
+    pub fn runtime(&self) -> &FheRuntime {
+        &self.runtime
+    }
+
+    /// Unwrap the underlying contract.
+    pub fn into_inner(self) -> Contract<M> {
+        self.contract
+    }
+}
+
+impl<M> Deref for FheContract<M> {
+    type Target = Contract<M>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.contract
+    }
+}
+
+/// Decrypt a value, unless it is empty.
+fn decrypt_if_set(
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+    bytes: &Bytes,
+) -> Result<Option<U256>> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    fhe::decrypt(runtime, private_key, bytes).map(Some)
+}
+
+/// Wait until contract code is deployed at `address`, e.g. a CREATE2 address predicted before the
+/// deployment was submitted. Returns [`Error::Timeout`] if there is still no code after `timeout`.
+pub async fn wait_for_code<M: Middleware + 'static>(
+    provider: &M,
+    address: Address,
+    timeout: Duration,
+) -> Result<()> {
+    let poll = async {
+        loop {
+            let code = provider
+                .get_code(address, None)
+                .await
+                .map_err(middleware_error)?;
+            if !code.is_empty() {
+                return Ok(());
+            }
+            tokio::time::sleep(provider.provider().get_interval()).await;
+        }
+    };
+    tokio::time::timeout(timeout, poll)
+        .await
+        .unwrap_or(Err(Error::Timeout(timeout)))
+}
+
+/// Check that the code deployed at `address` has the expected keccak256 hash, e.g. to refuse to
+/// interact with a contract which was unexpectedly upgraded or replaced. Returns `false` if there
+/// is no code at `address` at all.
+pub async fn verify_bytecode<M: Middleware + 'static>(
+    provider: &M,
+    address: Address,
+    expected_hash: [u8; 32],
+) -> Result<bool> {
+    let code = provider
+        .get_code(address, None)
+        .await
+        .map_err(middleware_error)?;
+    Ok(!code.is_empty() && keccak256(&code) == expected_hash)
+}
+
+/// The gas to write one 32 byte word of previously empty contract storage, i.e. a cold `SSTORE`
+/// from zero to a nonzero value.
+const STORAGE_WORD_GAS: u64 = 22_100;
+
+/// Estimate how many ciphertexts the size of `sample` can be submitted and stored in one block,
+/// e.g. to size batches of encrypted submissions.
+///
+/// Each ciphertext is priced as the node's gas estimate for a transaction carrying it as calldata,
+/// plus the cost of storing it as a fresh `bytes` value. This is conservative, as the fixed cost of
+/// a transaction is counted once per ciphertext, and ignores any computation the receiving
+/// contract does.
+pub async fn max_ciphertexts_per_block<M: Middleware + 'static>(
+    client: &M,
+    sample: &Ciphertext,
+) -> Result<usize> {
+    let data = sample.as_bytes()?;
+    let len = data.len();
+    let tx: TypedTransaction = TransactionRequest::new()
+        .to(Address::zero())
+        .data(data)
+        .into();
+    let submit_gas = client
+        .estimate_gas(&tx, None)
+        .await
+        .map_err(middleware_error)?;
+    let block = client
+        .get_block(BlockNumber::Latest)
+        .await
+        .map_err(middleware_error)?
+        .ok_or_else(|| anyhow::anyhow!("the node has no latest block"))?;
+    Ok(ciphertexts_per_gas(block.gas_limit, submit_gas, len))
+}
+
+/// How many ciphertexts of `len` bytes, each costing `submit_gas` to submit, can be submitted and
+/// stored within `gas_limit`.
+fn ciphertexts_per_gas(gas_limit: U256, submit_gas: U256, len: usize) -> usize {
+    // A long `bytes` value occupies its length slot plus one slot per 32 bytes of data.
+    let words = 1 + (len as u64).div_ceil(32);
+    let per_ciphertext = submit_gas + U256::from(words) * STORAGE_WORD_GAS;
+    (gas_limit / per_ciphertext).low_u64() as usize
+}
+
+/// The intrinsic gas of a zero byte of calldata.
+const ZERO_BYTE_GAS: u64 = 4;
+/// The intrinsic gas of a nonzero byte of calldata.
+const NONZERO_BYTE_GAS: u64 = 16;
+
+/// The intrinsic gas of sending `bytes` as calldata, i.e. 4 per zero byte and 16 per nonzero byte,
+/// e.g. to compare the fixed overhead of a serialized ciphertext across encodings before
+/// submitting it. This excludes the base cost of the transaction and any execution gas.
+pub fn calldata_gas(bytes: &Bytes) -> u64 {
+    bytes
+        .iter()
+        .map(|&b| {
+            if b == 0 {
+                ZERO_BYTE_GAS
+            } else {
+                NONZERO_BYTE_GAS
+            }
+        })
+        .sum()
+}
+
+/// Reconstruct and decrypt an encrypted mapping from the events a contract emits on each write,
+/// e.g. to snapshot a mapping which has no getter for all of its entries.
+///
+/// `event` is queried for logs between `from_block` and `to_block` inclusive, and `entry` extracts
+/// the mapping key and encrypted value from each log. When a key is written more than once, the
+/// value from the latest log wins.
+pub async fn decrypt_mapping_events<B, M, D, K, F>(
+    event: Event<B, M, D>,
+    from_block: u64,
+    to_block: u64,
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+    entry: F,
+) -> Result<HashMap<K, U256>>
+where
+    B: Borrow<M>,
+    M: Middleware + 'static,
+    D: EthLogDecode,
+    K: Eq + Hash,
+    F: FnMut(D) -> (K, Bytes),
+{
+    let logs = event
+        .from_block(from_block)
+        .to_block(to_block)
+        .query_with_meta()
+        .await
+        .map_err(anyhow::Error::new)?;
+    decrypt_latest(logs, runtime, private_key, entry)
+}
+
+/// Keep the latest value written to each key, then decrypt them.
+fn decrypt_latest<D, K: Eq + Hash>(
+    mut logs: Vec<(D, LogMeta)>,
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+    mut entry: impl FnMut(D) -> (K, Bytes),
+) -> Result<HashMap<K, U256>> {
+    logs.sort_by_key(|(_, meta)| (meta.block_number, meta.log_index));
+    let mut latest = HashMap::new();
+    for (log, _) in logs {
+        let (key, value) = entry(log);
+        latest.insert(key, value);
+    }
+    latest
+        .into_iter()
+        .map(|(key, value)| Ok((key, fhe::decrypt(runtime, private_key, &value)?)))
+        .collect()
+}
+
+/// A log classified by [`decode_logs`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedLog {
+    /// A log of one of the events in the ABI.
+    Event(DecodedEvent),
+    /// A log which matches no event in the ABI, e.g. one emitted by another contract, as is.
+    Raw(Log),
+}
+
+/// A log decoded as one of the events in an ABI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedEvent {
+    /// The name of the event, e.g. `Transfer`.
+    pub name: String,
+    /// The names and values of the event's parameters, in declaration order.
+    pub params: Vec<(String, Token)>,
+    /// The log the event was decoded from, e.g. for its block number.
+    pub log: Log,
+}
+
+impl DecodedEvent {
+    /// The `bytes` parameters of the event which hold serialized ciphertexts, by name. Indexed
+    /// `bytes` parameters are only logged as a hash, so they never appear here.
+    pub fn ciphertexts(&self) -> Vec<(&str, Ciphertext)> {
+        self.params
+            .iter()
+            .filter_map(|(name, token)| match token {
+                Token::Bytes(bytes) => Ciphertext::from_bytes(&bytes.clone().into())
+                    .ok()
+                    .map(|ciphertext| (name.as_str(), ciphertext)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Classify a batch of logs by their first topic and decode each into the matching event of `abi`,
+/// e.g. to process a mixed stream of events from an FHE contract. Logs matching no event, including
+/// anonymous events, and logs which fail to decode are returned as [`DecodedLog::Raw`].
+pub fn decode_logs(abi: &Abi, logs: &[Log]) -> Vec<DecodedLog> {
+    logs.iter()
+        .map(|log| match decode_log(abi, log) {
+            Some(event) => DecodedLog::Event(event),
+            None => DecodedLog::Raw(log.clone()),
+        })
+        .collect()
+}
+
+fn decode_log(abi: &Abi, log: &Log) -> Option<DecodedEvent> {
+    let topic0 = log.topics.first()?;
+    let event = abi
+        .events()
+        .find(|event| !event.anonymous && event.signature() == *topic0)?;
+    let decoded = event
+        .parse_log(RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        })
+        .ok()?;
+    Some(DecodedEvent {
+        name: event.name.clone(),
+        params: decoded
+            .params
+            .into_iter()
+            .map(|param| (param.name, param.value))
+            .collect(),
+        log: log.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::{Http, Provider},
+        types::{Address, H256, U64},
+    };
+
+    use super::*;
+    use crate::{tests::runtime, Ciphertext, Unsigned256};
+
+    #[test]
+    fn constructor_arg_is_bytes() {
+        let runtime = runtime();
+        let (public_key, _) = runtime.generate_keys().unwrap();
+
+        let token = constructor_arg(&public_key).unwrap();
+        assert_eq!(token, Token::Bytes(public_key.as_bytes().unwrap().to_vec()));
+    }
+
+    #[test]
+    fn decodes_structs_with_encrypted_fields() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(9), &public_key).unwrap();
+        let data = ethers::abi::encode(&[
+            Token::Uint(U256::from(1)),
+            Token::Bytes(ciphertext.as_bytes().unwrap().to_vec()),
+        ]);
+
+        let (id, value): (U256, Encrypted<Ciphertext>) = decode_abi(&data).unwrap();
+        assert_eq!(id, U256::from(1));
+        let value: Unsigned256 = runtime.decrypt(&value, &private_key).unwrap();
+        assert_eq!(value, Unsigned256::from(9));
+
+        // The fields are the wrong way around.
+        assert!(decode_abi::<(Encrypted<Ciphertext>, U256)>(&data).is_err());
+        // The bytes are not a ciphertext.
+        let data = ethers::abi::encode(&[Token::Uint(U256::one()), Token::Bytes(vec![1, 2, 3])]);
+        assert!(decode_abi::<(U256, Encrypted<Ciphertext>)>(&data).is_err());
+    }
+
+    #[test]
+    fn encodes_calls_with_fhe_arguments() {
+        let runtime = runtime();
+        let (public_key, _) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(4), &public_key).unwrap();
+        let to = Token::Address(ethers::types::Address::repeat_byte(1));
+
+        let calldata = encode_call(
+            "function transfer(address to, bytes amount)",
+            &[to.clone().into(), (&ciphertext).into()],
+        )
+        .unwrap();
+        assert_eq!(
+            calldata[..4],
+            ethers::utils::id("transfer(address,bytes)")[..]
+        );
+        let (address, amount): (ethers::types::Address, Encrypted<Ciphertext>) =
+            decode_abi(&calldata[4..]).unwrap();
+        assert_eq!(Token::Address(address), to);
+        assert_eq!(amount.as_bytes().unwrap(), ciphertext.as_bytes().unwrap());
+
+        // The arguments are the wrong way around.
+        assert!(encode_call(
+            "transfer(address,bytes)",
+            &[(&ciphertext).into(), to.into()]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn distinguishes_unset_from_zero() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let zero = fhe::encrypt(&runtime, &public_key, U256::zero()).unwrap();
+
+        assert_eq!(
+            decrypt_if_set(&runtime, &private_key, &zero).unwrap(),
+            Some(U256::zero())
+        );
+        assert_eq!(
+            decrypt_if_set(&runtime, &private_key, &Bytes::new()).unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_for_code() {
+        let node = crate::testing::Node {
+            poll_interval: Some(Duration::from_millis(10)),
+            ..crate::testing::Node::spawn()
+        };
+        let provider = node.provider();
+        let address = Address::repeat_byte(0x42);
+
+        let result = wait_for_code(&provider, address, Duration::from_millis(100)).await;
+        assert!(matches!(result, Err(Error::Timeout(_))));
+
+        provider
+            .request::<_, ()>("anvil_setCode", (address, Bytes::from(vec![0x00])))
+            .await
+            .unwrap();
+        wait_for_code(&provider, address, Duration::from_secs(5))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verifies_bytecode_hash() {
+        let node = crate::testing::Node::default();
+        let provider = node.provider();
+        let address = Address::repeat_byte(0x42);
+        let code = Bytes::from(vec![0x60, 0x00]);
+        let hash = keccak256(&code);
+        assert!(!verify_bytecode(&provider, address, hash).await.unwrap());
+
+        provider
+            .request::<_, ()>("anvil_setCode", (address, code))
+            .await
+            .unwrap();
+        assert!(verify_bytecode(&provider, address, hash).await.unwrap());
+        assert!(!verify_bytecode(&provider, address, [0; 32]).await.unwrap());
+    }
+
+    /// Install runtime code at `address` which returns the ABI encoding of `bytes`, copied from
+    /// the end of the code, whatever function is called.
+    async fn set_code_returning(provider: &Provider<Http>, address: Address, bytes: &[u8]) {
+        let returned = ethers::abi::encode(&[Token::Bytes(bytes.to_vec())]);
+        let len = (returned.len() as u32).to_be_bytes();
+        let mut code = vec![0x63];
+        code.extend_from_slice(&len);
+        code.extend_from_slice(&[0x63, 0, 0, 0, 21, 0x60, 0, 0x39, 0x63]);
+        code.extend_from_slice(&len);
+        code.extend_from_slice(&[0x60, 0, 0xf3]);
+        code.extend_from_slice(&returned);
+        provider
+            .request::<_, ()>("anvil_setCode", (address, Bytes::from(code)))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verifies_onchain_public_key() {
+        let runtime = runtime();
+        let (public_key, _) = runtime.generate_keys().unwrap();
+        let (other_public_key, _) = runtime.generate_keys().unwrap();
+        let node = crate::testing::Node::default();
+        let provider = Arc::new(node.provider());
+        let address = Address::repeat_byte(0x44);
+        set_code_returning(&provider, address, &public_key.as_bytes().unwrap()).await;
+
+        let abi = ethers::abi::parse_abi(&["function publicKey() view returns (bytes)"]).unwrap();
+        let contract = Contract::new(address, abi, provider.clone());
+        let call = || contract.method::<_, Bytes>("publicKey", ()).unwrap();
+        assert!(verify_onchain_public_key(call(), &public_key)
+            .await
+            .unwrap());
+        assert!(!verify_onchain_public_key(call(), &other_public_key)
+            .await
+            .unwrap());
+
+        // The contract stores something other than a public key.
+        set_code_returning(&provider, address, &[1, 2, 3]).await;
+        assert!(verify_onchain_public_key(call(), &public_key)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn fhe_contract_decrypts_reads() {
+        let runtime = Arc::new(runtime());
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let ciphertext = fhe::encrypt(&runtime, &public_key, U256::from(77)).unwrap();
+        let node = crate::testing::Node::default();
+        let provider = Arc::new(node.provider());
+        let address = Address::repeat_byte(0x43);
+        set_code_returning(&provider, address, &ciphertext).await;
+
+        let abi = ethers::abi::parse_abi(&["function balance() view returns (bytes)"]).unwrap();
+        let contract =
+            FheContract::new(Contract::new(address, abi, provider), runtime, private_key);
+        assert_eq!(contract.address(), address);
+        assert_eq!(
+            contract.call_decrypted("balance", ()).await.unwrap(),
+            Some(U256::from(77))
+        );
+        assert!(contract.call_decrypted("missing", ()).await.is_err());
+        assert_eq!(
+            format!("{:?}", contract.private_key),
+            "PrivateKey(REDACTED)"
+        );
+    }
+
+    #[test]
+    fn divides_gas_limit_between_ciphertexts() {
+        // 100 bytes take 4 words of storage plus the length slot.
+        let per_ciphertext = 30_000 + 5 * STORAGE_WORD_GAS;
+        let gas_limit = U256::from(per_ciphertext * 7 + 1);
+        assert_eq!(ciphertexts_per_gas(gas_limit, 30_000.into(), 100), 7);
+        assert_eq!(ciphertexts_per_gas(U256::from(1), 30_000.into(), 100), 0);
+    }
+
+    #[test]
+    fn decodes_mixed_logs() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let amount = runtime.encrypt(Unsigned256::from(6), &public_key).unwrap();
+        let abi = ethers::abi::parse_abi(&[
+            "event Deposited(address indexed from, bytes amount)",
+            "event Reset(uint256 round)",
+        ])
+        .unwrap();
+        let from = Address::repeat_byte(0x11);
+
+        let deposited = Log {
+            topics: vec![
+                abi.event("Deposited").unwrap().signature(),
+                H256::from(from),
+            ],
+            data: ethers::abi::encode(&[Token::Bytes(amount.as_bytes().unwrap().to_vec())]).into(),
+            ..Log::default()
+        };
+        let reset = Log {
+            topics: vec![abi.event("Reset").unwrap().signature()],
+            data: ethers::abi::encode(&[Token::Uint(3.into())]).into(),
+            ..Log::default()
+        };
+        let unknown = Log {
+            topics: vec![H256::repeat_byte(0x22)],
+            ..Log::default()
+        };
+        let truncated = Log {
+            data: Bytes::new(),
+            ..reset.clone()
+        };
+
+        let decoded = decode_logs(
+            &abi,
+            &[deposited, reset, unknown.clone(), truncated.clone()],
+        );
+        assert_eq!(decoded.len(), 4);
+        match &decoded[0] {
+            DecodedLog::Event(event) => {
+                assert_eq!(event.name, "Deposited");
+                assert_eq!(event.params[0], ("from".to_owned(), Token::Address(from)));
+                let ciphertexts = event.ciphertexts();
+                assert_eq!(ciphertexts.len(), 1);
+                assert_eq!(ciphertexts[0].0, "amount");
+                let value: Unsigned256 = runtime.decrypt(&ciphertexts[0].1, &private_key).unwrap();
+                assert_eq!(value, Unsigned256::from(6));
+            }
+            log => panic!("unexpected log {:?}", log),
+        }
+        match &decoded[1] {
+            DecodedLog::Event(event) => {
+                assert_eq!(event.name, "Reset");
+                assert_eq!(
+                    event.params,
+                    vec![("round".to_owned(), Token::Uint(3.into()))]
+                );
+                assert!(event.ciphertexts().is_empty());
+            }
+            log => panic!("unexpected log {:?}", log),
+        }
+        assert_eq!(decoded[2], DecodedLog::Raw(unknown));
+        assert_eq!(decoded[3], DecodedLog::Raw(truncated));
+    }
+
+    #[test]
+    fn prices_calldata_bytes() {
+        assert_eq!(calldata_gas(&Bytes::new()), 0);
+        assert_eq!(calldata_gas(&Bytes::from(vec![0, 1, 0, 0xff])), 40);
+
+        let runtime = runtime();
+        let (public_key, _) = runtime.generate_keys().unwrap();
+        let bytes = runtime
+            .encrypt(Unsigned256::from(1), &public_key)
+            .unwrap()
+            .as_bytes()
+            .unwrap();
+        let len = bytes.len() as u64;
+        let gas = calldata_gas(&bytes);
+        assert!(gas >= len * ZERO_BYTE_GAS && gas <= len * NONZERO_BYTE_GAS);
+    }
+
+    #[tokio::test]
+    async fn estimates_ciphertexts_per_block() {
+        let runtime = runtime();
+        let (public_key, _) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(1), &public_key).unwrap();
+        let node = crate::testing::Node::default();
+        let provider = node.provider();
+
+        let count = max_ciphertexts_per_block(&provider, &ciphertext)
+            .await
+            .unwrap();
+        let gas_limit = provider
+            .get_block(BlockNumber::Latest)
+            .await
+            .unwrap()
+            .unwrap()
+            .gas_limit;
+        let len = ciphertext.as_bytes().unwrap().len() as u64;
+        // At the very least, every ciphertext has to be stored.
+        assert!(U256::from(count) * U256::from(len / 32 * STORAGE_WORD_GAS) <= gas_limit);
+    }
+
+    fn meta(block_number: u64, log_index: u64) -> LogMeta {
+        LogMeta {
+            address: Address::zero(),
+            block_number: U64::from(block_number),
+            block_hash: H256::zero(),
+            transaction_hash: H256::zero(),
+            transaction_index: U64::zero(),
+            log_index: U256::from(log_index),
+        }
+    }
+
+    #[test]
+    fn decrypt_latest_keeps_last_write() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let encrypt = |x: u64| {
+            runtime
+                .encrypt(Unsigned256::from(x), &public_key)
+                .unwrap()
+                .as_bytes()
+                .unwrap()
+        };
+        // Out of order, as if merged from several queries.
+        let logs = vec![
+            (("alice", encrypt(3)), meta(2, 0)),
+            (("bob", encrypt(5)), meta(1, 1)),
+            (("alice", encrypt(1)), meta(1, 0)),
+            (("alice", encrypt(2)), meta(1, 2)),
+        ];
+
+        let mapping = decrypt_latest(logs, &runtime, &private_key, |entry| entry).unwrap();
+        assert_eq!(mapping.len(), 2);
+        assert_eq!(mapping["alice"], U256::from(3));
+        assert_eq!(mapping["bob"], U256::from(5));
+    }
+}
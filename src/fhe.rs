@@ -0,0 +1,673 @@
+//! This module offers helpers for working with encrypted values returned from contracts.
+
+use ethers::{
+    core::rand::{thread_rng, Rng},
+    types::{Bytes, U256},
+    utils::keccak256,
+};
+use serde::{Deserialize, Serialize};
+use sunscreen::{
+    fhe_program,
+    types::{Cipher, Type, TypeName},
+    CompiledFheProgram, Compiler, InnerCiphertext, RuntimeError, SchemeType,
+};
+
+use super::{
+    AsBytes, AsNum, Ciphertext, Error, FheRuntime, Params, PrivateKey, PublicKey, Result,
+    Unsigned256,
+};
+
+/// Evaluate an expression, reporting how long it took as the given `timing::Operation` when the
+/// `timed` feature is enabled.
+macro_rules! timed {
+    ($operation:ident, $e:expr) => {{
+        #[cfg(feature = "timed")]
+        let start = std::time::Instant::now();
+        let result = $e;
+        #[cfg(feature = "timed")]
+        crate::timing::record(crate::timing::Operation::$operation, start.elapsed());
+        result
+    }};
+}
+
+/// Encrypt and serialize an [`Unsigned256`], e.g. for a contract argument.
+pub fn encrypt(runtime: &FheRuntime, public_key: &PublicKey, value: U256) -> Result<Bytes> {
+    let ciphertext = timed!(Encrypt, runtime.encrypt(value.to(), public_key))?;
+    timed!(Serialize, ciphertext.as_bytes())
+}
+
+/// Encrypt an [`Unsigned256`] under a freshly generated, one-time keypair, e.g. for protocols in
+/// which every message uses a throwaway key. Returns the ciphertext along with the ephemeral
+/// public and private keys; discard the private key once the value has been decrypted.
+pub fn encrypt_ephemeral(
+    runtime: &FheRuntime,
+    value: U256,
+) -> Result<(Ciphertext, PublicKey, PrivateKey)> {
+    let (public_key, private_key) = runtime.generate_keys()?;
+    let ciphertext = timed!(Encrypt, runtime.encrypt(value.to(), &public_key))?;
+    Ok((ciphertext, public_key, private_key))
+}
+
+/// Deserialize and decrypt an encrypted [`Unsigned256`], e.g. from a contract return value.
+pub fn decrypt(runtime: &FheRuntime, private_key: &PrivateKey, bytes: &Bytes) -> Result<U256> {
+    decrypt_keeping_ciphertext(runtime, private_key, bytes).map(|(_, value)| value)
+}
+
+/// Decrypt like [`decrypt`], also returning the deserialized ciphertext.
+pub(crate) fn decrypt_keeping_ciphertext(
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+    bytes: &Bytes,
+) -> Result<(Ciphertext, U256)> {
+    let ciphertext = timed!(Deserialize, Ciphertext::from_bytes(bytes))?;
+    let value: Unsigned256 = timed!(Decrypt, runtime.decrypt(&ciphertext, private_key))?;
+    Ok((ciphertext, value.to()))
+}
+
+/// Encrypt a boolean, e.g. an access-control flag. Sunscreen has no encrypted boolean type, so
+/// booleans are encrypted as an [`Unsigned256`] zero or one, which contracts can combine with
+/// ordinary encrypted arithmetic (e.g. multiplying two flags computes their logical and).
+pub fn bool_to_ciphertext(
+    runtime: &FheRuntime,
+    public_key: &PublicKey,
+    value: bool,
+) -> Result<Ciphertext> {
+    let value = Unsigned256::from(u64::from(value));
+    Ok(timed!(Encrypt, runtime.encrypt(value, public_key))?)
+}
+
+/// Decrypt a boolean encrypted by [`bool_to_ciphertext`]. Any value other than zero or one is an
+/// error rather than being treated as true, since it indicates the ciphertext is not a flag (or
+/// that a contract computed one incorrectly).
+pub fn ciphertext_to_bool(
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+    ciphertext: &Ciphertext,
+) -> Result<bool> {
+    let value: Unsigned256 = timed!(Decrypt, runtime.decrypt(ciphertext, private_key))?;
+    let value: U256 = value.to();
+    if value == U256::zero() {
+        Ok(false)
+    } else if value == U256::one() {
+        Ok(true)
+    } else {
+        Err(anyhow::anyhow!("encrypted boolean decrypted to {}, expected 0 or 1", value).into())
+    }
+}
+
+/// Decrypt like [`decrypt`], with a private key that is only loaded for the operation, e.g. fetched
+/// from a secrets manager on demand.
+///
+/// The ciphertext is deserialized before `loader` is called, and the key is dropped as soon as the
+/// value is decrypted. SEAL clears the memory holding secret keys when they are dropped.
+pub fn decrypt_with_loader(
+    runtime: &FheRuntime,
+    loader: impl FnOnce() -> Result<PrivateKey>,
+    bytes: &Bytes,
+) -> Result<U256> {
+    let ciphertext = timed!(Deserialize, Ciphertext::from_bytes(bytes))?;
+    let private_key = loader()?;
+    let value: Unsigned256 = timed!(Decrypt, runtime.decrypt(&ciphertext, &private_key))?;
+    drop(private_key);
+    Ok(value.to())
+}
+
+/// Check that a public and private key belong to the same keypair, e.g. at startup after loading
+/// them from separate files, by encrypting a random value with one and decrypting it with the
+/// other.
+///
+/// Only a failure to encrypt is an error. A private key from another keypair typically fails to
+/// decrypt at all (e.g. with [`RuntimeError::TooMuchNoise`]) rather than decrypting to the wrong
+/// value, so any decryption failure means the keys do not match.
+pub fn is_matching_keypair(
+    runtime: &FheRuntime,
+    public_key: &PublicKey,
+    private_key: &PrivateKey,
+) -> Result<bool> {
+    let value = U256(thread_rng().gen()).to();
+    let ciphertext = runtime.encrypt(value, public_key)?;
+    match runtime.decrypt::<Unsigned256>(&ciphertext, private_key) {
+        Ok(decrypted) => Ok(decrypted == value),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Generate a keypair, retrying up to `attempts` times in total if generation fails, e.g. in an
+/// automated provisioning job that must ride out transient failures. Returns the error of the last
+/// attempt if every attempt fails, or an error if `attempts` is zero.
+pub fn generate_keypair_retrying(
+    runtime: &FheRuntime,
+    attempts: usize,
+) -> Result<(PublicKey, PrivateKey)> {
+    let mut last_error = None;
+    for _ in 0..attempts {
+        match runtime.generate_keys() {
+            Ok(keys) => return Ok(keys),
+            Err(e) => last_error = Some(e.into()),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| anyhow::anyhow!("no key generation attempts were made").into()))
+}
+
+/// Generate `count` independent keypairs, e.g. to provision FHE identities for a batch of new
+/// users. With the `parallel` feature, the keypairs are generated on all cores.
+pub fn generate_keypairs(
+    runtime: &FheRuntime,
+    count: usize,
+) -> Result<Vec<(PublicKey, PrivateKey)>> {
+    #[cfg(feature = "parallel")]
+    use rayon::prelude::*;
+    #[cfg(feature = "parallel")]
+    let range = (0..count).into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let range = 0..count;
+    range.map(|_| Ok(runtime.generate_keys()?)).collect()
+}
+
+/// Decrypt a batch of encrypted [`Unsigned256`]s, returning the values in the same order, e.g. a
+/// page of encrypted results. With the `parallel` feature, the ciphertexts are decrypted on all
+/// cores.
+///
+/// Returns [`Error::Decrypt`] with the index of a ciphertext that failed to decrypt. With the
+/// `parallel` feature, this is not necessarily the first failure.
+pub fn decrypt_many(
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+    ciphertexts: &[Ciphertext],
+) -> Result<Vec<U256>> {
+    #[cfg(feature = "parallel")]
+    use rayon::prelude::*;
+    #[cfg(feature = "parallel")]
+    let items = ciphertexts.par_iter().enumerate();
+    #[cfg(not(feature = "parallel"))]
+    let items = ciphertexts.iter().enumerate();
+    items
+        .map(|(index, ciphertext)| {
+            let value: Unsigned256 = timed!(Decrypt, runtime.decrypt(ciphertext, private_key))
+                .map_err(|e| Error::Decrypt {
+                    index,
+                    source: Box::new(e.into()),
+                })?;
+            Ok(value.to())
+        })
+        .collect()
+}
+
+#[fhe_program(scheme = "bfv")]
+fn sub(a: Cipher<Unsigned256>, b: Cipher<Unsigned256>) -> Cipher<Unsigned256> {
+    a - b
+}
+
+/// The homomorphic subtraction run by [`sub_ciphertexts`], compiled for one set of parameters.
+/// Compiling is slow, so construct this once and reuse it when subtracting many times.
+pub struct SubProgram {
+    program: CompiledFheProgram,
+}
+
+impl SubProgram {
+    /// Compile the subtraction program for `params`.
+    pub fn new(params: &Params) -> Result<Self> {
+        let app = Compiler::new()
+            .fhe_program(sub)
+            .with_params(params)
+            .compile()?;
+        let program = app
+            .get_fhe_program(sub)
+            .expect("the application was compiled with this program")
+            .clone();
+        Ok(Self { program })
+    }
+
+    /// Homomorphically subtract encrypted [`Unsigned256`] `b` from `a`, like [`sub_ciphertexts`].
+    ///
+    /// Returns [`RuntimeError::ParameterMismatch`] if the program was compiled for other parameters
+    /// than the runtime's, or if either ciphertext was not encrypted under them.
+    pub fn run(
+        &self,
+        runtime: &FheRuntime,
+        public_key: &PublicKey,
+        a: &Ciphertext,
+        b: &Ciphertext,
+    ) -> Result<Ciphertext> {
+        if self.program.metadata.params != *runtime.params() {
+            return Err(RuntimeError::ParameterMismatch.into());
+        }
+        ensure_params(runtime, a)?;
+        ensure_params(runtime, b)?;
+        let mut outputs = runtime.run(&self.program, vec![a.clone(), b.clone()], public_key)?;
+        Ok(outputs.remove(0))
+    }
+}
+
+/// Homomorphically subtract encrypted [`Unsigned256`] `b` from `a` without decrypting either,
+/// e.g. to compute the change between two encrypted balances. This compiles the subtraction
+/// program on every call; use a [`SubProgram`] to subtract repeatedly.
+///
+/// Returns [`RuntimeError::ParameterMismatch`] if either ciphertext was not encrypted under the
+/// runtime's parameters.
+pub fn sub_ciphertexts(
+    runtime: &FheRuntime,
+    public_key: &PublicKey,
+    a: &Ciphertext,
+    b: &Ciphertext,
+) -> Result<Ciphertext> {
+    SubProgram::new(runtime.params())?.run(runtime, public_key, a, b)
+}
+
+/// Check that a ciphertext was encrypted under the runtime's parameters.
+fn ensure_params(runtime: &FheRuntime, ciphertext: &Ciphertext) -> Result<()> {
+    let InnerCiphertext::Seal(inner) = &ciphertext.inner;
+    if inner.iter().any(|c| c.params != *runtime.params()) {
+        return Err(RuntimeError::ParameterMismatch.into());
+    }
+    Ok(())
+}
+
+/// Metadata describing a serialized ciphertext, read without deserializing the ciphertext itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FheMetadata {
+    /// The encrypted data type, including the version of Sunscreen that produced it.
+    pub data_type: Type,
+    /// The FHE scheme the ciphertext was encrypted under.
+    pub scheme: SchemeType,
+    /// The keccak256 hash of the parameters the ciphertext was encrypted under.
+    pub params_hash: [u8; 32],
+}
+
+impl FheMetadata {
+    /// Whether the ciphertext was produced by a version of Sunscreen that is semver-compatible
+    /// with the one this crate uses.
+    pub fn is_compatible(&self) -> bool {
+        let current = Unsigned256::type_name().version;
+        let found = &self.data_type.version;
+        if current.major == 0 {
+            found.major == 0 && found.minor == current.minor
+        } else {
+            found.major == current.major
+        }
+    }
+}
+
+/// The leading fields of a serialized [`Ciphertext`], mirroring its bincode layout.
+#[derive(Deserialize)]
+struct CiphertextHeader {
+    data_type: Type,
+    inner: InnerHeader,
+}
+
+#[derive(Deserialize)]
+enum InnerHeader {
+    Seal(SealHeader),
+}
+
+/// The length of the ciphertext list, followed by the parameters of the first ciphertext.
+#[derive(Deserialize)]
+struct SealHeader {
+    _len: u64,
+    params: Params,
+}
+
+/// Read the metadata of a serialized ciphertext without deserializing the ciphertext itself, e.g.
+/// to route ciphertexts of unknown origin to the right runtime. Check
+/// [`FheMetadata::is_compatible`] before attempting to deserialize the ciphertext.
+pub fn peek_fhe_metadata(bytes: &Bytes) -> Result<FheMetadata> {
+    let header: CiphertextHeader = bincode::deserialize(bytes)?;
+    let InnerHeader::Seal(seal) = header.inner;
+    Ok(FheMetadata {
+        data_type: header.data_type,
+        scheme: seal.params.scheme_type,
+        params_hash: params_hash(&seal.params),
+    })
+}
+
+/// The keccak256 hash of the canonical byte encoding of some parameters.
+pub(crate) fn params_hash(params: &Params) -> [u8; 32] {
+    keccak256(params.to_bytes())
+}
+
+/// The keccak256 hash of a runtime's parameters, as reported in [`FheMetadata::params_hash`]. Pin
+/// this to detect parameter drift between deployments, see [`assert_params_hash`].
+pub fn runtime_params_hash(runtime: &FheRuntime) -> [u8; 32] {
+    params_hash(runtime.params())
+}
+
+/// Check that a runtime uses the expected parameters, e.g. in a health check confirming that every
+/// node of a service uses identical FHE parameters. Returns [`Error::ParamsHash`] on a mismatch.
+pub fn assert_params_hash(runtime: &FheRuntime, expected: [u8; 32]) -> Result<()> {
+    let found = runtime_params_hash(runtime);
+    if found != expected {
+        return Err(Error::ParamsHash { expected, found });
+    }
+    Ok(())
+}
+
+/// An iterator adapter which lazily decrypts encrypted [`Unsigned256`] values, yielding one
+/// result per item. This allows streaming through a large encrypted dataset with bounded memory.
+///
+/// A value that fails to decrypt yields an error without ending the iteration. To stop at the
+/// first error instead, collect into a `Result<Vec<_>>`.
+pub struct DecryptIter<'a, I> {
+    inner: I,
+    runtime: &'a FheRuntime,
+    private_key: &'a PrivateKey,
+}
+
+impl<'a, I: Iterator<Item = Bytes>> DecryptIter<'a, I> {
+    /// Wrap an iterator of encrypted values.
+    pub fn new<T: IntoIterator<IntoIter = I>>(
+        inner: T,
+        runtime: &'a FheRuntime,
+        private_key: &'a PrivateKey,
+    ) -> Self {
+        Self {
+            inner: inner.into_iter(),
+            runtime,
+            private_key,
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = Bytes>> Iterator for DecryptIter<'a, I> {
+    type Item = Result<U256>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.inner.next()?;
+        Some(decrypt(self.runtime, self.private_key, &bytes))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// The keccak256 hash of a public key's [`AsBytes`] encoding, identifying the key without sharing
+/// it in full.
+pub fn key_fingerprint(public_key: &PublicKey) -> Result<[u8; 32]> {
+    Ok(keccak256(public_key.as_bytes()?))
+}
+
+/// A ciphertext addressed to a particular recipient, e.g. for routing encrypted values between
+/// parties off-chain. Use [`AsBytes`] to serialize it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    /// The [`key_fingerprint`] of the public key the ciphertext is encrypted under.
+    pub recipient: [u8; 32],
+    /// When the envelope was sealed, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub ciphertext: Ciphertext,
+}
+
+impl Envelope {
+    /// Address a ciphertext encrypted under `recipient`, timestamped with the current time.
+    pub fn seal(ciphertext: Ciphertext, recipient: &PublicKey) -> Result<Self> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(anyhow::Error::new)?
+            .as_secs();
+        Ok(Self {
+            recipient: key_fingerprint(recipient)?,
+            timestamp,
+            ciphertext,
+        })
+    }
+
+    /// Take the ciphertext out of the envelope, checking that it is addressed to `recipient`.
+    pub fn open(self, recipient: &PublicKey) -> Result<Ciphertext> {
+        if key_fingerprint(recipient)? != self.recipient {
+            return Err(anyhow::anyhow!("envelope is addressed to a different key").into());
+        }
+        Ok(self.ciphertext)
+    }
+}
+
+impl AsBytes for Envelope {
+    fn from_bytes(bytes: &Bytes) -> Result<Self> {
+        crate::deserialize_untrusted(bytes)
+    }
+
+    fn as_bytes(&self) -> Result<Bytes> {
+        Ok(bincode::serialize(self)?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::runtime;
+
+    #[test]
+    fn ephemeral_keys_are_fresh() {
+        let runtime = runtime();
+        let (ciphertext, public_key, private_key) =
+            encrypt_ephemeral(&runtime, U256::from(21)).unwrap();
+        let value: Unsigned256 = runtime.decrypt(&ciphertext, &private_key).unwrap();
+        assert_eq!(value.to(), U256::from(21));
+
+        let (_, other_public_key, other_private_key) =
+            encrypt_ephemeral(&runtime, U256::from(21)).unwrap();
+        assert!(other_public_key != public_key);
+        assert!(!is_matching_keypair(&runtime, &public_key, &other_private_key).unwrap());
+    }
+
+    #[test]
+    fn booleans_roundtrip_strictly() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        for flag in [false, true] {
+            let ciphertext = bool_to_ciphertext(&runtime, &public_key, flag).unwrap();
+            assert_eq!(
+                ciphertext_to_bool(&runtime, &private_key, &ciphertext).unwrap(),
+                flag
+            );
+        }
+
+        let two = runtime.encrypt(Unsigned256::from(2), &public_key).unwrap();
+        assert!(ciphertext_to_bool(&runtime, &private_key, &two).is_err());
+    }
+
+    #[test]
+    fn checks_pinned_params_hash() {
+        let runtime = runtime();
+        let pinned = runtime_params_hash(&runtime);
+        assert_params_hash(&runtime, pinned).unwrap();
+        assert!(matches!(
+            assert_params_hash(&runtime, [0; 32]),
+            Err(Error::ParamsHash { found, .. }) if found == pinned
+        ));
+    }
+
+    #[test]
+    fn decrypts_many_in_order() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let ciphertexts = (0..5u64)
+            .map(|x| runtime.encrypt(Unsigned256::from(x), &public_key).unwrap())
+            .collect::<Vec<_>>();
+
+        let values = decrypt_many(&runtime, &private_key, &ciphertexts).unwrap();
+        assert_eq!(values, (0..5u64).map(U256::from).collect::<Vec<_>>());
+
+        // A ciphertext under other parameters fails to decrypt.
+        let other = FheRuntime::new(&Params {
+            plain_modulus: runtime.params().plain_modulus * 2,
+            ..runtime.params().clone()
+        })
+        .unwrap();
+        let (other_key, _) = other.generate_keys().unwrap();
+        let mut ciphertexts = ciphertexts;
+        ciphertexts[3] = other.encrypt(Unsigned256::from(1), &other_key).unwrap();
+        assert!(matches!(
+            decrypt_many(&runtime, &private_key, &ciphertexts),
+            Err(Error::Decrypt { index: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn decrypt_keeps_ciphertext() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let bytes = encrypt(&runtime, &public_key, U256::from(12)).unwrap();
+
+        let (ciphertext, value) =
+            decrypt_keeping_ciphertext(&runtime, &private_key, &bytes).unwrap();
+        assert_eq!(value, U256::from(12));
+        assert_eq!(ciphertext.as_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn decrypts_with_loaded_key() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let bytes = encrypt(&runtime, &public_key, U256::from(8)).unwrap();
+
+        let mut loads = 0;
+        let value = decrypt_with_loader(
+            &runtime,
+            || {
+                loads += 1;
+                Ok(private_key.clone())
+            },
+            &bytes,
+        )
+        .unwrap();
+        assert_eq!(value, U256::from(8));
+        assert_eq!(loads, 1);
+
+        let missing = || Err(anyhow::anyhow!("secret not found").into());
+        assert!(decrypt_with_loader(&runtime, missing, &bytes).is_err());
+    }
+
+    #[test]
+    fn matches_keypairs() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let (other_public_key, other_private_key) = runtime.generate_keys().unwrap();
+
+        assert!(is_matching_keypair(&runtime, &public_key, &private_key).unwrap());
+        // The wrong key under the same parameters fails to decrypt rather than mismatching params.
+        assert!(!is_matching_keypair(&runtime, &other_public_key, &private_key).unwrap());
+        assert!(!is_matching_keypair(&runtime, &public_key, &other_private_key).unwrap());
+
+        let other = FheRuntime::new(&Params {
+            plain_modulus: runtime.params().plain_modulus * 2,
+            ..runtime.params().clone()
+        })
+        .unwrap();
+        let (_, mismatched_private_key) = other.generate_keys().unwrap();
+        assert!(!is_matching_keypair(&runtime, &public_key, &mismatched_private_key).unwrap());
+    }
+
+    #[test]
+    fn retries_key_generation() {
+        let runtime = runtime();
+        let (public_key, private_key) = generate_keypair_retrying(&runtime, 3).unwrap();
+        assert!(is_matching_keypair(&runtime, &public_key, &private_key).unwrap());
+        assert!(generate_keypair_retrying(&runtime, 0).is_err());
+    }
+
+    #[test]
+    fn generates_independent_keypairs() {
+        let runtime = runtime();
+
+        let keypairs = generate_keypairs(&runtime, 3).unwrap();
+        assert_eq!(keypairs.len(), 3);
+        for (i, (public_key, private_key)) in keypairs.iter().enumerate() {
+            assert!(is_matching_keypair(&runtime, public_key, private_key).unwrap());
+            assert!(keypairs[..i].iter().all(|(other, _)| other != public_key));
+        }
+    }
+
+    #[test]
+    fn subtracts_ciphertexts() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let a = runtime.encrypt(Unsigned256::from(10), &public_key).unwrap();
+        let b = runtime.encrypt(Unsigned256::from(3), &public_key).unwrap();
+
+        let diff = sub_ciphertexts(&runtime, &public_key, &a, &b).unwrap();
+        let value: Unsigned256 = runtime.decrypt(&diff, &private_key).unwrap();
+        assert_eq!(value, Unsigned256::from(7));
+
+        // A compiled program can be reused.
+        let program = SubProgram::new(runtime.params()).unwrap();
+        let diff = program.run(&runtime, &public_key, &a, &b).unwrap();
+        let diff = program.run(&runtime, &public_key, &diff, &b).unwrap();
+        let value: Unsigned256 = runtime.decrypt(&diff, &private_key).unwrap();
+        assert_eq!(value, Unsigned256::from(4));
+
+        let other = FheRuntime::new(&Params {
+            plain_modulus: runtime.params().plain_modulus * 2,
+            ..runtime.params().clone()
+        })
+        .unwrap();
+        let (other_key, _) = other.generate_keys().unwrap();
+        let mismatched = other.encrypt(Unsigned256::from(1), &other_key).unwrap();
+        for (a, b) in [(&a, &mismatched), (&mismatched, &b)] {
+            assert!(matches!(
+                sub_ciphertexts(&runtime, &public_key, a, b),
+                Err(Error::Runtime(RuntimeError::ParameterMismatch))
+            ));
+        }
+        assert!(matches!(
+            program.run(&other, &other_key, &mismatched, &mismatched),
+            Err(Error::Runtime(RuntimeError::ParameterMismatch))
+        ));
+    }
+
+    #[test]
+    fn peeks_ciphertext_metadata() {
+        let runtime = runtime();
+        let (public_key, _) = runtime.generate_keys().unwrap();
+        let bytes = runtime
+            .encrypt(Unsigned256::from(7), &public_key)
+            .unwrap()
+            .as_bytes()
+            .unwrap();
+
+        let metadata = peek_fhe_metadata(&bytes).unwrap();
+        assert_eq!(metadata.scheme, SchemeType::Bfv);
+        assert_eq!(metadata.params_hash, keccak256(runtime.params().to_bytes()));
+        assert!(metadata.data_type.is_encrypted);
+        assert!(metadata.is_compatible());
+
+        assert!(peek_fhe_metadata(&Bytes::from(vec![1, 2, 3])).is_err());
+    }
+
+    #[test]
+    fn decrypt_iter_continues_past_errors() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let encrypt = |x: u64| {
+            runtime
+                .encrypt(Unsigned256::from(x), &public_key)
+                .unwrap()
+                .as_bytes()
+                .unwrap()
+        };
+        let items = vec![encrypt(1), Bytes::from(vec![0xde, 0xad]), encrypt(3)];
+
+        let results = DecryptIter::new(items, &runtime, &private_key).collect::<Vec<_>>();
+        assert_eq!(results.len(), 3);
+        assert_eq!(*results[0].as_ref().unwrap(), U256::from(1));
+        assert!(results[1].is_err());
+        assert_eq!(*results[2].as_ref().unwrap(), U256::from(3));
+    }
+
+    #[test]
+    fn envelope_roundtrips_to_recipient() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let (other_key, _) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(9), &public_key).unwrap();
+
+        let envelope = Envelope::seal(ciphertext, &public_key).unwrap();
+        assert!(envelope.timestamp > 0);
+        let envelope = Envelope::from_bytes(&envelope.as_bytes().unwrap()).unwrap();
+        assert!(envelope.clone().open(&other_key).is_err());
+
+        let opened = envelope.open(&public_key).unwrap();
+        let value: Unsigned256 = runtime.decrypt(&opened, &private_key).unwrap();
+        assert_eq!(value, Unsigned256::from(9));
+    }
+}
@@ -1,15 +1,46 @@
-use std::{fs::File, path::Path, str::FromStr, sync::Arc};
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
+#[cfg(feature = "compression")]
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use batch::{BatchHttp, BatchOptions};
+use bincode::Options;
+use crypto_bigint::Encoding;
+#[cfg(feature = "ipc")]
+use ethers::providers::Ipc;
 use ethers::{
-    abi::{self, token::Tokenizer},
+    abi,
     prelude::{k256, SignerMiddleware},
     providers::{Http, Provider},
     signers::{self, LocalWallet, Wallet},
-    types::{Bytes, U256},
+    types::{Address, Bytes, TxHash, H256, U256},
+    utils::{hex, keccak256, to_checksum},
 };
+use serde::{de::DeserializeOwned, Serialize};
+pub mod batch;
+pub mod config;
+pub mod contract;
+pub mod fhe;
+pub mod proto;
+#[cfg(feature = "ws")]
+pub mod reconnect;
+pub mod sign;
+pub mod store;
 pub mod testing;
 pub mod testnet;
-pub use sunscreen::{types::bfv::*, Ciphertext, FheRuntime, PrivateKey, PublicKey};
+#[cfg(feature = "timed")]
+pub mod timing;
+pub mod tx;
+pub use seal_fhe::{GaloisKeys, RelinearizationKeys};
+pub use sunscreen::{
+    types::bfv::*, Ciphertext, FheRuntime, Params, PrivateKey, PublicKey, WithContext,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -17,31 +48,310 @@ pub enum Error {
     Abi(#[from] abi::Error),
     #[error("Bincode conversion error: {0}")]
     Conversion(#[from] bincode::Error),
+    #[error("FHE compiler error: {0}")]
+    Compiler(#[from] sunscreen::Error),
+    #[error("Unsupported compression algorithm: {0}")]
+    Compression(u8),
+    #[error("Base64 decoding error: {0}")]
+    Decode(#[from] base64::DecodeError),
+    #[error("HTTP error{}: {body}", status.map(|s| format!(" ({})", s)).unwrap_or_default())]
+    Http {
+        /// The response status, if a response was received at all.
+        status: Option<reqwest::StatusCode>,
+        /// The response body, or a description of the failure if there was no response.
+        body: String,
+    },
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("FHE runtime error: {0}")]
+    Runtime(#[from] sunscreen::RuntimeError),
     #[error("Wallet error: {0}")]
     Wallet(#[from] signers::WalletError),
+    #[error("Transaction already known to the node")]
+    AlreadyKnown,
+    #[error("Transaction {index} of batch failed: {source}")]
+    Batch { index: usize, source: Box<Error> },
+    #[error("Config field {field} is invalid: {reason}")]
+    Config { field: String, reason: String },
+    #[error("Decryption of item {index} failed: {source}")]
+    Decrypt { index: usize, source: Box<Error> },
+    #[error("Transaction {0:?} was dropped from the mempool")]
+    Dropped(TxHash),
+    #[error("Execution reverted{}", reason.as_ref().map(|r| format!(": {}", r)).unwrap_or_default())]
+    Revert {
+        /// The decoded reason, if the revert data is a standard Solidity error.
+        reason: Option<String>,
+        /// The raw revert data.
+        data: Bytes,
+    },
+    #[error("Transaction {0:?} was removed from its block by a chain reorganization")]
+    Reorged(TxHash),
+    #[error("Transaction {0:?} reverted")]
+    Reverted(TxHash),
+    #[error("Timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("Unit error: {0}")]
+    Units(String),
+    #[error("Unsupported format version {found}, expected {supported}")]
+    Version { found: u32, supported: u32 },
+    #[error("Invalid transaction nonce: {0}")]
+    Nonce(String),
+    #[error(
+        "FHE parameters hash {} does not match the expected {}",
+        hex::encode(found),
+        hex::encode(expected)
+    )]
+    ParamsHash { expected: [u8; 32], found: [u8; 32] },
+    #[error("Incorrect passphrase")]
+    Passphrase,
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http {
+            status: e.status(),
+            body: e.to_string(),
+        }
+    }
+}
+
 /// Convenient `Result` wrapper for [`Error`]s.
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// A convenient alias for a signing-capable client over an HTTP provider.
 pub type SignedMiddleware = SignerMiddleware<Arc<Provider<Http>>, Wallet<k256::ecdsa::SigningKey>>;
 
+/// A convenient alias for a signing-capable client over an IPC provider.
+#[cfg(feature = "ipc")]
+pub type IpcSignedMiddleware =
+    SignerMiddleware<Arc<Provider<Ipc>>, Wallet<k256::ecdsa::SigningKey>>;
+
+/// Connect to a node over IPC, i.e. a Unix domain socket or a Windows named pipe at `path`. For a
+/// node running on the same machine, this avoids the overhead of HTTP.
+#[cfg(feature = "ipc")]
+pub async fn ipc_provider<P: AsRef<Path>>(path: P) -> Result<Provider<Ipc>> {
+    Provider::connect_ipc(path)
+        .await
+        .map_err(tx::middleware_error)
+}
+
+/// How long providers constructed by this crate wait for a response to each RPC request by
+/// default. This bounds individual requests only, not waits for transactions to be mined.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Construct an HTTP provider whose requests fail after `timeout` rather than hanging on a stalled
+/// endpoint.
+pub(crate) fn http_provider(url: &str, timeout: Duration) -> Provider<Http> {
+    Provider::new(Http::new_with_client(
+        reqwest::Url::parse(url).unwrap(),
+        http_client(timeout),
+    ))
+}
+
+/// Construct a provider like [`http_provider`], which combines concurrent requests into batches.
+pub(crate) fn batched_http_provider(
+    url: &str,
+    timeout: Duration,
+    options: BatchOptions,
+) -> Provider<BatchHttp> {
+    Provider::new(BatchHttp::new(
+        reqwest::Url::parse(url).unwrap(),
+        http_client(timeout),
+        options,
+    ))
+}
+
+fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .expect("the HTTP client configuration is valid")
+}
+
 /// Our FHE types are encoded into [`Bytes`] in solidity contracts. This trait allows you to convert
 /// the bytes to and from the FHE types.
+///
+/// The default [`Self::as_bytes`] encoding is unversioned: it is the raw bincode contracts consume,
+/// so it carries no format version, and bytes written by an incompatible version of this crate
+/// fail somewhere in the middle of decoding. Use [`Self::as_bytes_versioned`] for values stored or
+/// exchanged off chain.
 // TODO maybe will want a bfv fractional impl?
 pub trait AsBytes: Sized {
-    /// Convert from bytes into an FHE type. This is useful for contract return values.
+    /// Convert from bytes into an FHE type. This is useful for contract return values. The bytes
+    /// are not checked for a format version.
     fn from_bytes(bytes: &Bytes) -> Result<Self>;
     /// Convert from an FHE type into bytes. This is useful for supplying contract method
-    /// arguments.
+    /// arguments. The bytes are not stamped with a format version.
     fn as_bytes(&self) -> Result<Bytes>;
+
+    /// Convert from bytes produced by [`Self::as_bytes_compressed`] into an FHE type.
+    #[cfg(feature = "compression")]
+    fn from_bytes_compressed(bytes: &Bytes) -> Result<Self> {
+        match bytes.first() {
+            Some(&COMPRESSION_NONE) => Self::from_bytes(&Bytes(bytes.0.slice(1..))),
+            Some(&COMPRESSION_ZSTD) => Self::from_bytes(&decompress_bounded(&bytes[1..])?.into()),
+            Some(&algorithm) => Err(Error::Compression(algorithm)),
+            None => Err(anyhow::anyhow!("missing compression header").into()),
+        }
+    }
+
+    /// Convert from an FHE type into zstd-compressed bytes, prefixed with a one byte header
+    /// identifying the compression algorithm. This is useful when persisting many values, where
+    /// space matters more than CPU time.
+    #[cfg(feature = "compression")]
+    fn as_bytes_compressed(&self) -> Result<Bytes> {
+        let bytes = self.as_bytes()?;
+        let mut compressed = vec![COMPRESSION_ZSTD];
+        zstd::stream::copy_encode(bytes.as_ref(), &mut compressed, 0)?;
+        Ok(compressed.into())
+    }
+
+    /// Convert from an FHE type into bytes framed with a metadata blob, e.g. a creation time or
+    /// label, which travels with the value. With empty metadata, this is the same as
+    /// [`Self::as_bytes`].
+    fn as_bytes_with_meta(&self, meta: &[u8]) -> Result<Bytes> {
+        let bytes = self.as_bytes()?;
+        if meta.is_empty() {
+            return Ok(bytes);
+        }
+        let meta_len = u32::try_from(meta.len())
+            .map_err(|_| anyhow::anyhow!("metadata of {} bytes is too long", meta.len()))?;
+        let mut framed = Vec::with_capacity(META_MAGIC.len() + 4 + meta.len() + bytes.len());
+        framed.extend_from_slice(&META_MAGIC);
+        framed.extend_from_slice(&meta_len.to_le_bytes());
+        framed.extend_from_slice(meta);
+        framed.extend_from_slice(&bytes);
+        Ok(framed.into())
+    }
+
+    /// Convert from bytes produced by [`Self::as_bytes_with_meta`] into an FHE type and its
+    /// metadata. Plain [`Self::as_bytes`] encodings are also accepted, with empty metadata.
+    fn from_bytes_with_meta(bytes: &Bytes) -> Result<(Self, Vec<u8>)> {
+        let framed = match bytes.strip_prefix(&META_MAGIC[..]) {
+            Some(framed) => framed,
+            None => return Ok((Self::from_bytes(bytes)?, Vec::new())),
+        };
+        let (meta_len, rest) = match framed {
+            [a, b, c, d, rest @ ..] => (u32::from_le_bytes([*a, *b, *c, *d]) as usize, rest),
+            _ => return Err(anyhow::anyhow!("truncated metadata frame").into()),
+        };
+        if rest.len() < meta_len {
+            return Err(anyhow::anyhow!("truncated metadata frame").into());
+        }
+        let (meta, value) = rest.split_at(meta_len);
+        Ok((Self::from_bytes(&value.to_vec().into())?, meta.to_vec()))
+    }
+
+    /// Convert from an FHE type into bytes stamped with [`BYTES_FORMAT_VERSION`], e.g. for values
+    /// exchanged between parties which may run different versions of this crate. Contracts expect
+    /// the plain [`Self::as_bytes`] encoding, so use this only off chain.
+    fn as_bytes_versioned(&self) -> Result<Bytes> {
+        let bytes = self.as_bytes()?;
+        let mut versioned = Vec::with_capacity(VERSION_MAGIC.len() + 1 + bytes.len());
+        versioned.extend_from_slice(&VERSION_MAGIC);
+        versioned.push(BYTES_FORMAT_VERSION);
+        versioned.extend_from_slice(&bytes);
+        Ok(versioned.into())
+    }
+
+    /// Convert from bytes produced by [`Self::as_bytes_versioned`] into an FHE type. Returns
+    /// [`Error::Version`] if the bytes were written in another format version, rather than failing
+    /// somewhere in the middle of decoding them. Plain [`Self::as_bytes`] encodings, which predate
+    /// versioning, are also accepted.
+    fn from_bytes_versioned(bytes: &Bytes) -> Result<Self> {
+        let versioned = match bytes.strip_prefix(&VERSION_MAGIC[..]) {
+            Some(versioned) => versioned,
+            None => return Self::from_bytes(bytes),
+        };
+        match versioned.split_first() {
+            Some((&BYTES_FORMAT_VERSION, value)) => Self::from_bytes(&value.to_vec().into()),
+            Some((&found, _)) => Err(Error::Version {
+                found: found.into(),
+                supported: BYTES_FORMAT_VERSION.into(),
+            }),
+            None => Err(anyhow::anyhow!("missing format version").into()),
+        }
+    }
+}
+
+/// The format version stamped by [`AsBytes::as_bytes_versioned`]. This changes whenever the
+/// encoding of the FHE types changes incompatibly. It is not part of the plain
+/// [`AsBytes::as_bytes`] encoding.
+pub const BYTES_FORMAT_VERSION: u8 = 1;
+
+/// Marks bytes produced by [`AsBytes::as_bytes_versioned`], followed by the format version. Like
+/// [`META_MAGIC`], no bincode encoding of the FHE types starts with these bytes.
+const VERSION_MAGIC: [u8; 3] = [0xff, b'S', b'V'];
+
+/// Marks bytes produced by [`AsBytes::as_bytes_with_meta`]. No bincode encoding of the FHE types
+/// starts with these bytes, as they begin with a length prefix, which would be absurdly large.
+const META_MAGIC: [u8; 4] = [0xff, b'S', b'M', b'D'];
+
+/// A wire format for FHE types. [`AsBytes`] and [`AsFile`] always use [`Bincode`]; implement this
+/// trait and use [`AsCodec`] to exchange values in another format, e.g. MessagePack for interop
+/// with a non-Rust consumer.
+pub trait Codec {
+    /// Encode a value.
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>>;
+    /// Decode a value from bytes, which may be of unknown origin.
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T>;
+
+    /// Encode a value into a writer, e.g. a file.
+    fn encode_into<W: Write, T: Serialize>(mut writer: W, value: &T) -> Result<()> {
+        writer.write_all(&Self::encode(value)?)?;
+        Ok(())
+    }
+
+    /// Decode a value from a reader, e.g. a file.
+    fn decode_from<R: Read, T: DeserializeOwned>(mut reader: R) -> Result<T> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::decode(&bytes)
+    }
+}
+
+/// The default [`Codec`], used by [`AsBytes`] and [`AsFile`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        deserialize_untrusted(bytes)
+    }
+
+    fn encode_into<W: Write, T: Serialize>(writer: W, value: &T) -> Result<()> {
+        Ok(bincode::serialize_into(writer, value)?)
+    }
 }
 
+/// Convert FHE types to and from bytes and files in a chosen [`Codec`]. With [`Bincode`], these
+/// are equivalent to [`AsBytes`] and [`AsFile`].
+pub trait AsCodec: Sized {
+    /// Decode an FHE type from bytes in the format of `C`.
+    fn from_bytes_with<C: Codec>(bytes: &Bytes) -> Result<Self>;
+    /// Encode an FHE type as bytes in the format of `C`.
+    fn as_bytes_with<C: Codec>(&self) -> Result<Bytes>;
+    /// Read an FHE type from a file in the format of `C`.
+    fn read_with<C: Codec, P: AsRef<Path>>(path: P) -> Result<Self>;
+    /// Write an FHE type to a file in the format of `C`.
+    fn write_with<C: Codec, P: AsRef<Path>>(&self, path: P) -> Result<()>;
+}
+
+/// Header byte for uncompressed data in [`AsBytes::as_bytes_compressed`] encodings.
+#[cfg(feature = "compression")]
+const COMPRESSION_NONE: u8 = 0;
+/// Header byte for zstd-compressed data in [`AsBytes::as_bytes_compressed`] encodings.
+#[cfg(feature = "compression")]
+const COMPRESSION_ZSTD: u8 = 1;
+
 /// When generating keypairs, you'll need to save your private key (and it is often convenient to
 /// have your public key saved locally as well). For a CLI application, the natural way to store
 /// keys is in the filesystem.
@@ -52,6 +362,21 @@ pub trait AsFile: Sized {
     fn write<P: AsRef<Path>>(&self, path: P) -> Result<()>;
 }
 
+/// Public keys are often shared out of band, e.g. scanned from a QR code during onboarding. This
+/// trait offers a compact, URL-safe text encoding for that purpose.
+///
+/// Note that SEAL already compresses the key material itself, so most of the savings come from
+/// the surrounding bincode framing and the repeated scheme parameters. This trait is only
+/// available with the `compression` feature.
+#[cfg(feature = "compression")]
+pub trait AsCompact: Sized {
+    /// Parse an FHE type from its compact string form.
+    fn from_compact(compact: &str) -> Result<Self>;
+    /// Encode an FHE type as a compact string: zstd-compressed bincode, encoded as unpadded
+    /// base64url.
+    fn to_compact(&self) -> Result<String>;
+}
+
 /// Convert between ethers and sunscreen numeric types. This should be a bijection, hence
 /// the associated type. (Note: implicit assumption of 64-bit architecture!)
 pub trait AsNum {
@@ -72,64 +397,507 @@ impl AsNum for U256 {
     }
 }
 
+/// Convert [`Unsigned256`] to and from bytes with an explicit byte order. Unlike [`AsNum`], which
+/// copies the underlying 64-bit words (least significant word first) as-is, these conversions do not
+/// depend on any implicit word ordering, which makes them safe for bridging to other libraries.
+pub trait AsEndianBytes: Sized {
+    /// Decode from 32 big-endian bytes, i.e. most significant byte first.
+    fn from_be_bytes(bytes: [u8; 32]) -> Self;
+    /// Decode from 32 little-endian bytes, i.e. least significant byte first.
+    fn from_le_bytes(bytes: [u8; 32]) -> Self;
+    /// Encode as 32 big-endian bytes, i.e. most significant byte first. This matches the EVM's
+    /// encoding of a `uint256`.
+    fn to_be_bytes(&self) -> [u8; 32];
+    /// Encode as 32 little-endian bytes, i.e. least significant byte first.
+    fn to_le_bytes(&self) -> [u8; 32];
+}
+
+impl AsEndianBytes for Unsigned256 {
+    fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        Unsigned256::from(crypto_bigint::U256::from_be_bytes(bytes))
+    }
+
+    fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        Unsigned256::from(crypto_bigint::U256::from_le_bytes(bytes))
+    }
+
+    fn to_be_bytes(&self) -> [u8; 32] {
+        crypto_bigint::U256::from(*self).to_be_bytes()
+    }
+
+    fn to_le_bytes(&self) -> [u8; 32] {
+        crypto_bigint::U256::from(*self).to_le_bytes()
+    }
+}
+
+/// A denomination of ether, used when parsing and formatting amounts.
+///
+/// This implements [`FromStr`], so it can be used as a clap value, e.g. to let users pick the
+/// units amounts are displayed in. Besides the named units, a bare number of decimals such as
+/// `"6"` is accepted, up to [`MAX_DECIMALS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Unit {
+    Wei,
+    Gwei,
+    Ether,
+    /// An arbitrary denomination, worth `10^n` wei. Parsing and formatting amounts fails if `n`
+    /// exceeds [`MAX_DECIMALS`].
+    Decimals(u32),
+}
+
+/// The most decimal places a [`Unit`] may have, as `10^77` is the largest power of ten which fits
+/// in a [`U256`].
+pub const MAX_DECIMALS: u32 = 77;
+
+impl Unit {
+    /// The number of decimal places between this unit and wei.
+    pub fn decimals(&self) -> u32 {
+        match self {
+            Unit::Wei => 0,
+            Unit::Gwei => 9,
+            Unit::Ether => 18,
+            Unit::Decimals(n) => *n,
+        }
+    }
+
+    /// The number of decimal places, or [`Error::Units`] if there are more than
+    /// [`MAX_DECIMALS`]. Checked before padding amounts with zeros, so that a huge number of
+    /// decimals cannot allocate a huge string.
+    fn checked_decimals(&self) -> Result<usize> {
+        match self.decimals() {
+            n if n > MAX_DECIMALS => Err(Error::Units(format!(
+                "{} decimals exceed the maximum of {}",
+                n, MAX_DECIMALS
+            ))),
+            n => Ok(n as usize),
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "wei" => Unit::Wei,
+            "gwei" | "nano" | "nanoether" => Unit::Gwei,
+            "ether" => Unit::Ether,
+            other => {
+                let unit = Unit::Decimals(
+                    other
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("unknown unit {:?}", s))?,
+                );
+                unit.checked_decimals()?;
+                unit
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for Unit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unit::Wei => write!(f, "wei"),
+            Unit::Gwei => write!(f, "gwei"),
+            Unit::Ether => write!(f, "ether"),
+            Unit::Decimals(n) => write!(f, "{}", n),
+        }
+    }
+}
+
 /// Parses an ether value from a string.
 ///
-/// The amount can be tagged with a unit, e.g. "1ether". If the string represents an untagged
-/// amount (e.g. "100") then it is interpreted as wei.
+/// The amount can be tagged with a [`Unit`], e.g. "1ether" or "1.5 gwei". If the string represents
+/// an untagged amount (e.g. "100") then it is interpreted as wei.
+///
+/// Large decimal amounts may be written with separators: underscores between any two digits, as in
+/// "1_000_000", or commas grouping the integer part into thousands, as in "1,000,000gwei".
 ///
 /// This function can be useful as a clap `value_parser`.
 pub fn parse_ether_value(value: &str) -> Result<U256> {
-    Ok(if value.starts_with("0x") {
-        U256::from_str(value).map_err(anyhow::Error::new)?
-    } else {
-        U256::from(abi::token::LenientTokenizer::tokenize_uint(value)?)
+    if value.starts_with("0x") {
+        return Ok(U256::from_str(value).map_err(anyhow::Error::new)?);
+    }
+    let amount = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &value[amount.len()..] {
+        "" => Unit::Wei,
+        unit => unit.parse()?,
+    };
+    parse_units(&strip_separators(amount.trim_end())?, unit)
+}
+
+/// Remove the digit separators accepted by [`parse_ether_value`]. Commas must group thousands, so
+/// that e.g. "1,5" is rejected rather than read as 15.
+fn strip_separators(amount: &str) -> Result<String> {
+    let invalid = || anyhow::anyhow!("misplaced separator in amount {:?}", amount);
+    let bytes = amount.as_bytes();
+    let is_digit = |i: Option<usize>| i.and_then(|i| bytes.get(i)).is_some_and(u8::is_ascii_digit);
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'_' && !(is_digit(i.checked_sub(1)) && is_digit(Some(i + 1))) {
+            return Err(invalid().into());
+        }
+    }
+
+    let (integer, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if fraction.contains(',') {
+        return Err(invalid().into());
+    }
+    let mut groups = integer.split(',');
+    let first = groups.next().unwrap_or_default();
+    let is_group = |group: &str, len: std::ops::RangeInclusive<usize>| {
+        len.contains(&group.len()) && group.bytes().all(|b| b.is_ascii_digit())
+    };
+    if integer.contains(',') && !(is_group(first, 1..=3) && groups.all(|g| is_group(g, 3..=3))) {
+        return Err(invalid().into());
+    }
+
+    Ok(amount.replace(['_', ','], ""))
+}
+
+/// Parses an Ethereum address, with or without a `0x` prefix and surrounding whitespace.
+///
+/// All lowercase and all uppercase addresses are accepted as is. Mixed case addresses must carry a
+/// valid EIP-55 checksum, so that a mistyped address is rejected rather than silently accepted.
+///
+/// Like [`parse_ether_value`], this function can be useful as a clap `value_parser`.
+pub fn parse_address(value: &str) -> Result<Address> {
+    let value = value.trim();
+    let hex_digits = value.strip_prefix("0x").unwrap_or(value);
+    if hex_digits.len() != 40 || !hex_digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(anyhow::anyhow!("{:?} is not an address of 40 hex digits", value).into());
+    }
+    let address = Address::from_str(hex_digits).map_err(anyhow::Error::new)?;
+
+    let is_mixed_case = hex_digits.bytes().any(|b| b.is_ascii_lowercase())
+        && hex_digits.bytes().any(|b| b.is_ascii_uppercase());
+    if is_mixed_case {
+        let checksummed = to_checksum(&address, None);
+        if checksummed[2..] != *hex_digits {
+            return Err(anyhow::anyhow!(
+                "{:?} has an invalid checksum, did you mean {}?",
+                value,
+                checksummed
+            )
+            .into());
+        }
+    }
+    Ok(address)
+}
+
+/// Parses an untagged decimal amount denominated in `unit`, e.g. "1.5" ether, into wei.
+///
+/// Returns [`Error::Units`] if `unit` has more than [`MAX_DECIMALS`].
+pub fn parse_units(amount: &str, unit: Unit) -> Result<U256> {
+    let decimals = unit.checked_decimals()?;
+    let invalid = || anyhow::anyhow!("invalid {} amount {:?}", unit, amount);
+    let (integer, fraction) = match amount.split_once('.') {
+        Some((integer, fraction)) if !fraction.is_empty() => (integer, fraction),
+        Some(_) => return Err(invalid().into()),
+        None => (amount, ""),
+    };
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if integer.is_empty()
+        || !is_digits(integer)
+        || !is_digits(fraction)
+        || fraction.len() > decimals
+    {
+        return Err(invalid().into());
+    }
+    let digits = format!(
+        "{}{}{}",
+        integer,
+        fraction,
+        "0".repeat(decimals - fraction.len())
+    );
+    Ok(U256::from_dec_str(&digits).map_err(|_| invalid())?)
+}
+
+/// Formats an amount of wei as a decimal amount denominated in `unit`, without trailing zeros,
+/// e.g. "1.5" for 1.5 ether. The unit itself is not included.
+///
+/// Returns [`Error::Units`] if `unit` has more than [`MAX_DECIMALS`].
+pub fn format_units(value: U256, unit: Unit) -> Result<String> {
+    let decimals = unit.checked_decimals()?;
+    let digits = value.to_string();
+    let digits = format!(
+        "{}{}",
+        "0".repeat((decimals + 1).saturating_sub(digits.len())),
+        digits
+    );
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+    Ok(match fraction.trim_end_matches('0') {
+        "" => integer.to_owned(),
+        fraction => format!("{}.{}", integer, fraction),
     })
 }
 
-macro_rules! impl_bytes_via_bincode {
+/// Render an integer amount with a fixed number of decimal places, e.g. a token balance with 18
+/// decimals. The conversion is exact: no digits are rounded off, and trailing zeros of the
+/// fractional part are omitted. See [`format_units`].
+///
+/// Returns [`Error::Units`] if `decimals` exceeds [`MAX_DECIMALS`].
+pub fn u256_to_decimal_string(value: U256, decimals: u32) -> Result<String> {
+    format_units(value, Unit::Decimals(decimals))
+}
+
+/// Parse a decimal string into an integer amount with a fixed number of decimal places, the
+/// inverse of [`u256_to_decimal_string`].
+///
+/// Strings with more fractional digits than `decimals` are rejected rather than truncated, as are
+/// amounts that overflow a [`U256`]. Returns [`Error::Units`] if `decimals` exceeds
+/// [`MAX_DECIMALS`]. See [`parse_units`].
+pub fn decimal_string_to_u256(s: &str, decimals: u32) -> Result<U256> {
+    parse_units(s, Unit::Decimals(decimals))
+}
+
+/// Get a shared [`FheRuntime`] for the given parameters, constructing it on first use.
+///
+/// Runtimes are cached for the lifetime of the process, so concurrent handlers working under the
+/// same parameters all share a single instance.
+#[cfg(feature = "runtime-cache")]
+pub fn runtime_cache(params: &Params) -> Result<Arc<FheRuntime>> {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use ethers::prelude::Lazy;
+
+    static RUNTIMES: Lazy<Mutex<HashMap<Params, Arc<FheRuntime>>>> = Lazy::new(Default::default);
+
+    let mut runtimes = RUNTIMES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(runtime) = runtimes.get(params) {
+        return Ok(runtime.clone());
+    }
+    let runtime = Arc::new(FheRuntime::new(params)?);
+    runtimes.insert(params.clone(), runtime.clone());
+    Ok(runtime)
+}
+
+/// Compute the address a contract is deployed at when `deployer` creates it with the `CREATE2`
+/// opcode, e.g. to know an FHE contract's address before deploying it through a factory.
+pub fn compute_create2_address(deployer: Address, salt: [u8; 32], init_code: &Bytes) -> Address {
+    ethers::utils::get_create2_address(deployer, salt, init_code.clone())
+}
+
+/// Commit to a list of ciphertexts with a single Merkle root, e.g. to store on-chain for an
+/// auditable encrypted ledger.
+///
+/// Each leaf is the keccak256 hash of a ciphertext's [`AsBytes`] encoding, and each parent is the
+/// keccak256 hash of its left and right children concatenated (in that order), so the root depends
+/// on the order of `items`. On a level with an odd number of nodes, the last node is carried up
+/// unchanged. An empty list commits to the zero hash.
+pub fn commit_ciphertexts(items: &[Ciphertext]) -> Result<[u8; 32]> {
+    let leaves = items
+        .iter()
+        .map(|c| Ok(keccak256(c.as_bytes()?)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(merkle_root(leaves))
+}
+
+/// Convert a 32 byte hash, e.g. from [`commit_ciphertexts`], into an [`H256`], such as for a
+/// `bytes32` contract argument. The byte order is preserved: byte 0 of the array is byte 0 of the
+/// `bytes32`, which is the most significant byte when the value is read as a `uint256`.
+pub fn h256_from_bytes(bytes: [u8; 32]) -> H256 {
+    H256(bytes)
+}
+
+/// Convert an [`H256`] into a 32 byte array, preserving the byte order like [`h256_from_bytes`].
+pub fn h256_to_bytes(hash: H256) -> [u8; 32] {
+    hash.0
+}
+
+fn merkle_root(mut level: Vec<[u8; 32]>) -> [u8; 32] {
+    if level.is_empty() {
+        return [0; 32];
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => keccak256([*left, *right].concat()),
+                [odd] => *odd,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+macro_rules! impl_via_codec {
     ($($ty:ty),+) => {
         $(
+            impl AsCodec for $ty {
+                fn from_bytes_with<C: Codec>(bytes: &Bytes) -> Result<Self> {
+                    C::decode(bytes)
+                }
+
+                fn as_bytes_with<C: Codec>(&self) -> Result<Bytes> {
+                    Ok(C::encode(self)?.into())
+                }
+
+                fn read_with<C: Codec, P: AsRef<Path>>(path: P) -> Result<Self> {
+                    C::decode_from(File::open(path)?)
+                }
+
+                fn write_with<C: Codec, P: AsRef<Path>>(&self, path: P) -> Result<()> {
+                    C::encode_into(File::create(path)?, self)
+                }
+            }
+
             impl AsBytes for $ty {
                 fn from_bytes(bytes: &Bytes) -> Result<Self> {
-                    let val = bincode::deserialize(bytes)?;
-                    Ok(val)
+                    Self::from_bytes_with::<Bincode>(bytes)
                 }
 
                 fn as_bytes(&self) -> Result<Bytes> {
-                    let bytes = bincode::serialize(self)?.into();
-                    Ok(bytes)
+                    self.as_bytes_with::<Bincode>()
                 }
             }
-        )+
-    };
-}
-
-impl_bytes_via_bincode! {
-    PublicKey, PrivateKey, Ciphertext
-}
 
-macro_rules! impl_file_via_bincode {
-    ($($ty:ty),+) => {
-        $(
             impl AsFile for $ty {
                 fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
-                    let mut file = File::open(path)?;
-                    let val = bincode::deserialize_from(&mut file)?;
-                    Ok(val)
+                    Self::read_with::<Bincode, _>(path)
                 }
 
                 fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-                    let mut file = File::create(path)?;
-                    bincode::serialize_into(&mut file, &self)?;
-                    Ok(())
+                    self.write_with::<Bincode, _>(path)
                 }
             }
         )+
     };
 }
 
-impl_file_via_bincode! {
-    PublicKey, PrivateKey, Ciphertext
+// Evaluation keys are also part of a `PublicKey`, but are handled separately to allow caching the
+// expensive-to-generate keys on their own.
+impl_via_codec! {
+    PublicKey, PrivateKey, Ciphertext, WithContext<GaloisKeys>, WithContext<RelinearizationKeys>
+}
+
+/// Deserialize bincode from bytes of unknown origin. Length prefixes are bounded by the size of
+/// `bytes`, and a panic while decoding is reported as [`Error::Conversion`], so malformed input
+/// cannot crash the caller.
+///
+/// Catching the panic relies on unwinding: in a build with `panic = "abort"`, a panic while
+/// decoding still aborts the process.
+pub(crate) fn deserialize_untrusted<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    let options = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(bytes.len() as u64);
+    std::panic::catch_unwind(|| options.deserialize(bytes))
+        .unwrap_or_else(|_| {
+            Err(Box::new(bincode::ErrorKind::Custom(
+                "panicked while decoding".to_owned(),
+            )))
+        })
+        .map_err(Error::Conversion)
+}
+
+/// Decode `data` as each of the FHE types, discarding the results. This is the entry point for
+/// fuzzing [`AsBytes::from_bytes`], e.g. from a `cargo fuzz` target, and never panics unless
+/// built with `panic = "abort"`; see [`deserialize_untrusted`].
+#[cfg(any(test, fuzzing))]
+pub fn fuzz_from_bytes(data: &[u8]) {
+    let bytes = Bytes::from(data.to_vec());
+    let _ = Ciphertext::from_bytes(&bytes);
+    let _ = PublicKey::from_bytes(&bytes);
+    let _ = PrivateKey::from_bytes(&bytes);
+}
+
+/// A [`PrivateKey`] whose `Debug` and `Display` output is redacted, to keep it out of logs. The key
+/// is still available through [`std::ops::Deref`], and [`AsBytes`] and [`AsFile`] still produce
+/// the real key when explicitly requested.
+///
+/// Wallets do not need wrapping, as [`LocalWallet`]'s `Debug` output already omits the signing key.
+#[derive(Clone, PartialEq)]
+pub struct RedactedPrivateKey(pub PrivateKey);
+
+impl std::ops::Deref for RedactedPrivateKey {
+    type Target = PrivateKey;
+
+    fn deref(&self) -> &PrivateKey {
+        &self.0
+    }
+}
+
+impl From<PrivateKey> for RedactedPrivateKey {
+    fn from(key: PrivateKey) -> Self {
+        Self(key)
+    }
+}
+
+impl std::fmt::Debug for RedactedPrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PrivateKey(REDACTED)")
+    }
+}
+
+impl std::fmt::Display for RedactedPrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PrivateKey(REDACTED)")
+    }
+}
+
+impl AsBytes for RedactedPrivateKey {
+    fn from_bytes(bytes: &Bytes) -> Result<Self> {
+        PrivateKey::from_bytes(bytes).map(Self)
+    }
+
+    fn as_bytes(&self) -> Result<Bytes> {
+        self.0.as_bytes()
+    }
+}
+
+impl AsFile for RedactedPrivateKey {
+    fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        PrivateKey::read(path).map(Self)
+    }
+
+    fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.0.write(path)
+    }
+}
+
+/// The zstd compression level used by [`AsCompact`]. Keys are encoded rarely, so we favor size
+/// over speed.
+#[cfg(feature = "compression")]
+const COMPACT_ZSTD_LEVEL: i32 = 19;
+
+/// The most bytes [`AsBytes::from_bytes_compressed`] and [`AsCompact::from_compact`] decompress,
+/// far more than any key or ciphertext needs, so that small input cannot decompress to exhaust
+/// memory.
+#[cfg(feature = "compression")]
+const MAX_DECOMPRESSED_LEN: u64 = 64 << 20;
+
+/// Decompress zstd-compressed bytes, failing rather than producing more than
+/// [`MAX_DECOMPRESSED_LEN`] bytes.
+#[cfg(feature = "compression")]
+fn decompress_bounded(compressed: &[u8]) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    zstd::stream::Decoder::new(compressed)?
+        .take(MAX_DECOMPRESSED_LEN + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_DECOMPRESSED_LEN {
+        return Err(anyhow::anyhow!(
+            "compressed data decompresses to more than {} bytes",
+            MAX_DECOMPRESSED_LEN
+        )
+        .into());
+    }
+    Ok(bytes)
+}
+
+#[cfg(feature = "compression")]
+impl AsCompact for PublicKey {
+    fn from_compact(compact: &str) -> Result<Self> {
+        let compressed = URL_SAFE_NO_PAD.decode(compact)?;
+        deserialize_untrusted(&decompress_bounded(&compressed)?)
+    }
+
+    fn to_compact(&self) -> Result<String> {
+        let bytes = bincode::serialize(self)?;
+        let compressed = zstd::encode_all(bytes.as_slice(), COMPACT_ZSTD_LEVEL)?;
+        Ok(URL_SAFE_NO_PAD.encode(compressed))
+    }
 }
 
 impl AsFile for LocalWallet {
@@ -146,7 +914,567 @@ impl AsFile for LocalWallet {
     }
 }
 
+/// Prompt for a hex encoded wallet private key on the terminal with echo disabled, e.g. so a CLI
+/// can accept a pasted key without it appearing on screen or in the process list. The prompt is
+/// written to, and the key read from, the controlling terminal even if stdin is redirected.
+pub fn read_wallet_from_stdin(prompt: &str) -> Result<LocalWallet> {
+    let key = rpassword::prompt_password(prompt)?;
+    parse_wallet(&key)
+}
+
+/// Parse a hex encoded private key, with or without a `0x` prefix, ignoring surrounding
+/// whitespace.
+fn parse_wallet(key: &str) -> Result<LocalWallet> {
+    Ok(LocalWallet::from_str(key.trim())?)
+}
+
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use sunscreen::{fhe_program, types::Cipher, Compiler};
+
+    use super::*;
+
+    #[fhe_program(scheme = "bfv")]
+    fn add(a: Cipher<Unsigned256>, b: Cipher<Unsigned256>) -> Cipher<Unsigned256> {
+        a + b
+    }
+
+    #[fhe_program(scheme = "bfv")]
+    fn mul(a: Cipher<Unsigned256>, b: Cipher<Unsigned256>) -> Cipher<Unsigned256> {
+        a * b
+    }
+
+    /// Construct a runtime with parameters suitable for [`Unsigned256`] arithmetic.
+    pub(crate) fn runtime() -> FheRuntime {
+        let app = Compiler::new().fhe_program(add).compile().unwrap();
+        FheRuntime::new(app.params()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn http_provider_times_out() {
+        use ethers::providers::Middleware;
+
+        // Accept connections but never respond.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let provider = http_provider(&url, Duration::from_millis(100));
+
+        let start = std::time::Instant::now();
+        assert!(provider.get_block_number().await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn parses_ether_values() {
+        let gwei = U256::exp10(9);
+        assert_eq!(parse_ether_value("100").unwrap(), U256::from(100));
+        assert_eq!(parse_ether_value("0x10").unwrap(), U256::from(16));
+        assert_eq!(parse_ether_value("1gwei").unwrap(), gwei);
+        assert_eq!(
+            parse_ether_value("1.5 ether").unwrap(),
+            gwei * 1_500_000_000u64
+        );
+        assert_eq!(
+            parse_ether_value("0.000000000000000001ether").unwrap(),
+            U256::one()
+        );
+
+        assert_eq!(
+            parse_ether_value("1_000_000").unwrap(),
+            U256::from(1_000_000)
+        );
+        assert_eq!(
+            parse_ether_value("1,000,000gwei").unwrap(),
+            gwei * 1_000_000
+        );
+        assert_eq!(
+            parse_ether_value("12,345.000_5 gwei").unwrap(),
+            U256::from(12_345_000_500_000u64)
+        );
+
+        for invalid in [
+            "1,5 ether",
+            "1,0000",
+            "1000,000",
+            ",100",
+            "100,",
+            "1__0",
+            "_10",
+            "10_",
+            "1_.5 ether",
+            "1.5,0 ether",
+            "1_,000",
+            "0.1 wei",
+            "1. gwei",
+            ".1 gwei",
+            "2.1.1 gwei",
+            "1abc",
+            "-1",
+            "",
+        ] {
+            assert!(parse_ether_value(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let address = Address::from_str("b5f27c716e44ffe48fd6622983c651355ad8c75a").unwrap();
+        let checksummed = to_checksum(&address, None);
+        let lowercase = format!("{:?}", address);
+        assert_ne!(checksummed, lowercase);
+
+        for valid in [
+            checksummed.clone(),
+            lowercase.clone(),
+            lowercase[2..].to_owned(),
+            format!("0x{}", lowercase[2..].to_uppercase()),
+            format!("  {}\n", checksummed),
+        ] {
+            assert_eq!(parse_address(&valid).unwrap(), address, "{:?}", valid);
+        }
+
+        // Flip the case of one letter in the checksummed address.
+        let position = checksummed[2..]
+            .find(|c: char| c.is_ascii_alphabetic())
+            .unwrap()
+            + 2;
+        let mut mistyped = checksummed.clone().into_bytes();
+        mistyped[position] ^= 0x20;
+        let mistyped = String::from_utf8(mistyped).unwrap();
+        let error = parse_address(&mistyped).unwrap_err().to_string();
+        assert!(error.contains(&checksummed), "{}", error);
+
+        for invalid in [
+            "",
+            "0x",
+            &lowercase[..41],
+            "0xg5f27c716e44ffe48fd6622983c651355ad8c75a",
+        ] {
+            assert!(parse_address(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn units_parse_and_format() {
+        assert_eq!("Ether".parse::<Unit>().unwrap(), Unit::Ether);
+        assert_eq!("nano".parse::<Unit>().unwrap(), Unit::Gwei);
+        assert_eq!("6".parse::<Unit>().unwrap(), Unit::Decimals(6));
+        assert!("finney".parse::<Unit>().is_err());
+
+        let value = parse_units("1.25", Unit::Decimals(6)).unwrap();
+        assert_eq!(value, U256::from(1_250_000));
+        assert_eq!(format_units(value, Unit::Decimals(6)).unwrap(), "1.25");
+        assert_eq!(
+            format_units(U256::from(5), Unit::Ether).unwrap(),
+            "0.000000000000000005"
+        );
+        assert_eq!(format_units(U256::exp10(18), Unit::Ether).unwrap(), "1");
+        assert_eq!(format_units(U256::from(42), Unit::Wei).unwrap(), "42");
+    }
+
+    #[test]
+    fn units_reject_excessive_decimals() {
+        let max = Unit::Decimals(MAX_DECIMALS);
+        assert_eq!(MAX_DECIMALS.to_string().parse::<Unit>().unwrap(), max);
+        assert_eq!(parse_units("1", max).unwrap(), U256::exp10(77));
+        assert_eq!(
+            format_units(U256::MAX, max).unwrap().replace('.', ""),
+            U256::MAX.to_string()
+        );
+
+        let too_many = Unit::Decimals(u32::MAX);
+        assert!(matches!("4294967295".parse::<Unit>(), Err(Error::Units(_))));
+        assert!(matches!(parse_units("1", too_many), Err(Error::Units(_))));
+        assert!(matches!(
+            format_units(U256::one(), too_many),
+            Err(Error::Units(_))
+        ));
+    }
+
+    #[test]
+    fn evaluation_keys_roundtrip() {
+        let app = Compiler::new().fhe_program(mul).compile().unwrap();
+        let runtime = FheRuntime::new(app.params()).unwrap();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("relin.key");
+        public_key.relin_key.as_ref().unwrap().write(&path).unwrap();
+        let relin_key = WithContext::<RelinearizationKeys>::read(&path).unwrap();
+        let galois_key = public_key
+            .galois_key
+            .as_ref()
+            .map(|key| WithContext::<GaloisKeys>::from_bytes(&key.as_bytes().unwrap()).unwrap());
+        assert!(galois_key == public_key.galois_key);
+
+        // Multiplication relinearizes, so only works with the rehydrated relinearization keys.
+        let public_key = PublicKey {
+            relin_key: Some(relin_key),
+            galois_key,
+            ..public_key
+        };
+        let a = runtime.encrypt(Unsigned256::from(6), &public_key).unwrap();
+        let b = runtime.encrypt(Unsigned256::from(7), &public_key).unwrap();
+        let program = app.get_fhe_program(mul).unwrap();
+        let product = runtime
+            .run(program, vec![a, b], &public_key)
+            .unwrap()
+            .remove(0);
+        let product: Unsigned256 = runtime.decrypt(&product, &private_key).unwrap();
+        assert_eq!(product, Unsigned256::from(42));
+    }
+
+    #[test]
+    fn decimal_strings_roundtrip() {
+        let value = decimal_string_to_u256("1234.5678", 18).unwrap();
+        assert_eq!(value, U256::from(12_345_678) * U256::exp10(14));
+        assert_eq!(u256_to_decimal_string(value, 18).unwrap(), "1234.5678");
+        assert_eq!(
+            u256_to_decimal_string(U256::MAX, 0).unwrap(),
+            U256::MAX.to_string()
+        );
+
+        assert!(decimal_string_to_u256("0.123", 2).is_err());
+        let too_large = format!("{}0", U256::MAX);
+        assert!(decimal_string_to_u256(&too_large, 0).is_err());
+        assert!(matches!(
+            decimal_string_to_u256("1", 78),
+            Err(Error::Units(_))
+        ));
+        assert!(matches!(
+            u256_to_decimal_string(U256::one(), u32::MAX),
+            Err(Error::Units(_))
+        ));
+    }
+
+    #[test]
+    fn redacted_private_key_hides_secret() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let redacted = RedactedPrivateKey::from(private_key.clone());
+
+        assert_eq!(format!("{:?}", redacted), "PrivateKey(REDACTED)");
+        assert_eq!(redacted.to_string(), "PrivateKey(REDACTED)");
+        assert_eq!(
+            redacted.as_bytes().unwrap(),
+            private_key.as_bytes().unwrap()
+        );
+
+        let ciphertext = runtime.encrypt(Unsigned256::from(5), &public_key).unwrap();
+        let value: Unsigned256 = runtime.decrypt(&ciphertext, &redacted).unwrap();
+        assert_eq!(value, Unsigned256::from(5));
+    }
+
+    #[test]
+    fn endian_bytes_roundtrip() {
+        let mut be = [0u8; 32];
+        for (i, b) in be.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let mut le = be;
+        le.reverse();
+
+        let from_be = Unsigned256::from_be_bytes(be);
+        let from_le = Unsigned256::from_le_bytes(le);
+        assert_eq!(from_be, from_le);
+        assert_eq!(from_be.to_be_bytes(), be);
+        assert_eq!(from_be.to_le_bytes(), le);
+    }
+
+    #[test]
+    fn endian_bytes_agree_with_ethers() {
+        let value = U256::from_dec_str("1234567890123456789012345678901234567890").unwrap();
+        let unsigned = value.to();
+
+        let mut be = [0u8; 32];
+        value.to_big_endian(&mut be);
+        let mut le = [0u8; 32];
+        value.to_little_endian(&mut le);
+
+        assert_eq!(unsigned.to_be_bytes(), be);
+        assert_eq!(unsigned.to_le_bytes(), le);
+        assert_eq!(Unsigned256::from_be_bytes(be).to(), value);
+        assert_eq!(Unsigned256::from(1).to_be_bytes()[31], 1);
+        assert_eq!(Unsigned256::from(1).to_le_bytes()[0], 1);
+    }
+
+    #[cfg(feature = "runtime-cache")]
+    #[test]
+    fn runtime_cache_reuses_runtimes() {
+        let params = runtime().params().clone();
+        let a = runtime_cache(&params).unwrap();
+        let b = runtime_cache(&params).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+    }
+
+    #[tokio::test]
+    async fn create2_address_matches_deployment() {
+        use crate::{
+            testing::{Node, ALICE},
+            tx::{self, SendOptions},
+        };
+        use ethers::{providers::Middleware, types::TransactionRequest};
+
+        // A factory which deploys its calldata as init code via CREATE2 with a zero salt, and
+        // returns the address of the new contract.
+        let factory_runtime = "36600060003760003660006000f560005260206000f3";
+        let factory_init = format!("601680600b6000396000f3{}", factory_runtime);
+        // Init code for a one byte contract.
+        let init_code = Bytes::from_str("0x60016000f3").unwrap();
+
+        let node = Node::default();
+        let client = node.client(ALICE.clone());
+        let deploy = TransactionRequest::new().data(Bytes::from_str(&factory_init).unwrap());
+        let receipt = tx::send(&client, deploy, &SendOptions::default())
+            .await
+            .unwrap();
+        let factory = receipt.contract_address.unwrap();
+
+        let expected = compute_create2_address(factory, [0; 32], &init_code);
+        let create = TransactionRequest::new().to(factory).data(init_code);
+        let returned = tx::simulate(&client, create.clone()).await.unwrap();
+        assert_eq!(Address::from_slice(&returned[12..]), expected);
+
+        tx::send(&client, create, &SendOptions::default())
+            .await
+            .unwrap();
+        assert!(!client.get_code(expected, None).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn h256_conversions_preserve_byte_order() {
+        let mut bytes = [0; 32];
+        bytes[0] = 0x12;
+        bytes[31] = 0x34;
+
+        let hash = h256_from_bytes(bytes);
+        assert_eq!(h256_to_bytes(hash), bytes);
+        assert_eq!(format!("{:?}", hash), format!("0x12{}34", "00".repeat(30)));
+        // Byte 0 is the most significant when read as a `uint256`.
+        assert_eq!(
+            U256::from_big_endian(hash.as_bytes()),
+            (U256::from(0x12) << 248) + 0x34
+        );
+    }
+
+    #[test]
+    fn merkle_root_is_ordered() {
+        let (a, b, c) = (keccak256("a"), keccak256("b"), keccak256("c"));
+        let ab = keccak256([a, b].concat());
+
+        assert_eq!(merkle_root(vec![]), [0; 32]);
+        assert_eq!(merkle_root(vec![a]), a);
+        assert_eq!(merkle_root(vec![a, b]), ab);
+        assert_eq!(merkle_root(vec![a, b, c]), keccak256([ab, c].concat()));
+        assert_ne!(merkle_root(vec![b, a]), ab);
+    }
+
+    #[test]
+    fn ciphertext_commitment_is_deterministic() {
+        let runtime = runtime();
+        let (public_key, _) = runtime.generate_keys().unwrap();
+        let items = (0..3)
+            .map(|i| runtime.encrypt(Unsigned256::from(i), &public_key).unwrap())
+            .collect::<Vec<_>>();
+
+        let root = commit_ciphertexts(&items).unwrap();
+        assert_eq!(commit_ciphertexts(&items).unwrap(), root);
+
+        let reordered = [items[1].clone(), items[0].clone(), items[2].clone()];
+        assert_ne!(commit_ciphertexts(&reordered).unwrap(), root);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_bytes_roundtrip() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(42), &public_key).unwrap();
+
+        let compressed = ciphertext.as_bytes_compressed().unwrap();
+        assert_eq!(compressed[0], COMPRESSION_ZSTD);
+
+        let decompressed = Ciphertext::from_bytes_compressed(&compressed).unwrap();
+        let value: Unsigned256 = runtime.decrypt(&decompressed, &private_key).unwrap();
+        assert_eq!(value, Unsigned256::from(42));
+
+        let mut unknown = compressed.to_vec();
+        unknown[0] = 0xff;
+        assert!(matches!(
+            Ciphertext::from_bytes_compressed(&unknown.into()),
+            Err(Error::Compression(0xff))
+        ));
+        // Small input which decompresses to more than the limit is rejected.
+        let zeros = std::io::repeat(0).take(MAX_DECOMPRESSED_LEN + 1);
+        let mut bomb = vec![COMPRESSION_ZSTD];
+        zstd::stream::copy_encode(zeros, &mut bomb, 1).unwrap();
+        assert!(bomb.len() < 1 << 20);
+        assert!(Ciphertext::from_bytes_compressed(&bomb.into()).is_err());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn public_key_compact_roundtrip() {
+        let runtime = runtime();
+        let (public_key, _) = runtime.generate_keys().unwrap();
+
+        let compact = public_key.to_compact().unwrap();
+        assert!(compact
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert!(compact.len() < URL_SAFE_NO_PAD.encode(public_key.as_bytes().unwrap()).len());
+
+        let decoded = PublicKey::from_compact(&compact).unwrap();
+        assert!(decoded == public_key);
+
+        // A small string which decompresses to more than the limit is rejected.
+        let zeros = std::io::repeat(0).take(MAX_DECOMPRESSED_LEN + 1);
+        let bomb = URL_SAFE_NO_PAD.encode(zstd::encode_all(zeros, 1).unwrap());
+        assert!(bomb.len() < 1 << 20);
+        assert!(PublicKey::from_compact(&bomb).is_err());
+    }
+
+    #[test]
+    fn from_bytes_rejects_arbitrary_input() {
+        use ethers::core::rand::{thread_rng, Rng, RngCore};
+
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(1), &public_key).unwrap();
+        let valid = [
+            ciphertext.as_bytes().unwrap(),
+            public_key.as_bytes().unwrap(),
+            private_key.as_bytes().unwrap(),
+        ];
+
+        let mut rng = thread_rng();
+        for _ in 0..100 {
+            let mut data = vec![0; rng.gen_range(0..256)];
+            rng.fill_bytes(&mut data);
+            fuzz_from_bytes(&data);
+        }
+        // Corrupt valid encodings, which get much further through the decoders than random bytes.
+        for bytes in &valid {
+            for _ in 0..20 {
+                let mut data = bytes.to_vec();
+                let i = rng.gen_range(0..data.len());
+                data[i] = rng.gen();
+                data.truncate(rng.gen_range(i..=data.len()));
+                fuzz_from_bytes(&data);
+            }
+        }
+
+        let mut huge_length = vec![0; 8];
+        huge_length.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            Bincode::decode_from::<_, Ciphertext>(huge_length.as_slice()),
+            Err(Error::Conversion(_))
+        ));
+        assert!(matches!(
+            Ciphertext::from_bytes(&huge_length.into()),
+            Err(Error::Conversion(_))
+        ));
+    }
+
+    /// A [`Codec`] other than the default, to check that the format is pluggable.
+    struct Json;
+
+    impl Codec for Json {
+        fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+            Ok(serde_json::to_vec(value).map_err(anyhow::Error::new)?)
+        }
+
+        fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+            Ok(serde_json::from_slice(bytes).map_err(anyhow::Error::new)?)
+        }
+    }
+
+    #[test]
+    fn codecs_are_pluggable() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(6), &public_key).unwrap();
+
+        let json = ciphertext.as_bytes_with::<Json>().unwrap();
+        assert_eq!(json.first(), Some(&b'{'));
+        let decoded = Ciphertext::from_bytes_with::<Json>(&json).unwrap();
+        let value: Unsigned256 = runtime.decrypt(&decoded, &private_key).unwrap();
+        assert_eq!(value, Unsigned256::from(6));
+        assert!(Ciphertext::from_bytes(&json).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("public.json");
+        public_key.write_with::<Json, _>(&path).unwrap();
+        assert!(PublicKey::read_with::<Json, _>(&path).unwrap() == public_key);
+
+        // The default API is still bincode.
+        assert_eq!(
+            ciphertext.as_bytes().unwrap(),
+            ciphertext.as_bytes_with::<Bincode>().unwrap()
+        );
+    }
+
+    #[test]
+    fn parses_pasted_wallet_keys() {
+        use ethers::signers::Signer;
+
+        let key = "1c0eb5244c165957525ef389fc14fac4424feaaefabf87c7e4e15bcc7b425e15";
+        let address = Address::from_str("b5f27c716e44ffe48fd6622983c651355ad8c75a").unwrap();
+        assert_eq!(parse_wallet(key).unwrap().address(), address);
+        assert_eq!(
+            parse_wallet(&format!(" 0x{}\n", key)).unwrap().address(),
+            address
+        );
+        assert!(matches!(parse_wallet("not a key"), Err(Error::Wallet(_))));
+    }
+
+    #[test]
+    fn metadata_travels_with_bytes() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(5), &public_key).unwrap();
+
+        let framed = ciphertext.as_bytes_with_meta(b"label: rent").unwrap();
+        let (decoded, meta) = Ciphertext::from_bytes_with_meta(&framed).unwrap();
+        assert_eq!(meta, b"label: rent");
+        let value: Unsigned256 = runtime.decrypt(&decoded, &private_key).unwrap();
+        assert_eq!(value, Unsigned256::from(5));
+
+        // Without metadata, the encoding is the plain one.
+        let plain = ciphertext.as_bytes_with_meta(&[]).unwrap();
+        assert_eq!(plain, ciphertext.as_bytes().unwrap());
+        assert!(Ciphertext::from_bytes(&plain).is_ok());
+        let (_, meta) = Ciphertext::from_bytes_with_meta(&plain).unwrap();
+        assert!(meta.is_empty());
+
+        assert!(Ciphertext::from_bytes_with_meta(&framed.0.slice(..10).into()).is_err());
+    }
+
+    #[test]
+    fn versioned_bytes_check_format_version() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(5), &public_key).unwrap();
+
+        let versioned = ciphertext.as_bytes_versioned().unwrap();
+        assert_eq!(versioned[3], BYTES_FORMAT_VERSION);
+        let decoded = Ciphertext::from_bytes_versioned(&versioned).unwrap();
+        let value: Unsigned256 = runtime.decrypt(&decoded, &private_key).unwrap();
+        assert_eq!(value, Unsigned256::from(5));
+
+        // The default encoding is unversioned, and is still accepted.
+        let unversioned = ciphertext.as_bytes().unwrap();
+        assert!(!unversioned.starts_with(&VERSION_MAGIC));
+        assert!(Ciphertext::from_bytes_versioned(&unversioned).is_ok());
+
+        let mut future = versioned.to_vec();
+        future[3] = BYTES_FORMAT_VERSION + 1;
+        assert!(matches!(
+            Ciphertext::from_bytes_versioned(&future.into()),
+            Err(Error::Version { found, supported })
+                if found == u32::from(BYTES_FORMAT_VERSION) + 1
+                    && supported == u32::from(BYTES_FORMAT_VERSION)
+        ));
+        assert!(Ciphertext::from_bytes_versioned(&versioned.0.slice(..3).into()).is_err());
+    }
 }
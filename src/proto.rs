@@ -0,0 +1,129 @@
+//! This module offers a portable protobuf encoding of ciphertexts, for handing encrypted values to
+//! components not written in Rust. The schema lives in `proto/ciphertext.proto`.
+
+use ethers::types::Bytes;
+use sunscreen::SchemeType;
+
+use super::{
+    fhe::{peek_fhe_metadata, FheMetadata},
+    AsBytes, Ciphertext, Error, Result,
+};
+
+/// The current version of the protobuf schema.
+pub const PROTO_VERSION: u32 = 1;
+
+/// The `sunscreen_web3.v1.Ciphertext` protobuf message. Use [`prost::Message`] to encode and
+/// decode it.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProtoCiphertext {
+    /// The version of the schema; see [`PROTO_VERSION`].
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    /// The FHE scheme the value was encrypted under, e.g. `"bfv"`.
+    #[prost(string, tag = "2")]
+    pub scheme: String,
+    /// The keccak256 hash of the encryption parameters.
+    #[prost(bytes = "vec", tag = "3")]
+    pub params_hash: Vec<u8>,
+    /// The encrypted data type and the version of Sunscreen that produced it.
+    #[prost(string, tag = "4")]
+    pub data_type: String,
+    /// The ciphertext, encoded as by [`AsBytes::as_bytes`].
+    #[prost(bytes = "vec", tag = "5")]
+    pub payload: Vec<u8>,
+}
+
+/// Convert FHE types to and from their protobuf representation.
+pub trait AsProto: Sized {
+    /// Convert from a protobuf message into an FHE type.
+    fn from_proto(proto: &ProtoCiphertext) -> Result<Self>;
+    /// Convert from an FHE type into a protobuf message.
+    fn to_proto(&self) -> Result<ProtoCiphertext>;
+}
+
+impl AsProto for Ciphertext {
+    fn from_proto(proto: &ProtoCiphertext) -> Result<Self> {
+        if proto.version != PROTO_VERSION {
+            return Err(Error::Version {
+                found: proto.version,
+                supported: PROTO_VERSION,
+            });
+        }
+        let payload = Bytes::from(proto.payload.clone());
+        if metadata_fields(&peek_fhe_metadata(&payload)?) != metadata_fields_of(proto) {
+            return Err(anyhow::anyhow!("ciphertext metadata does not match its payload").into());
+        }
+        Ciphertext::from_bytes(&payload)
+    }
+
+    fn to_proto(&self) -> Result<ProtoCiphertext> {
+        let payload = self.as_bytes()?;
+        let (scheme, params_hash, data_type) = metadata_fields(&peek_fhe_metadata(&payload)?);
+        Ok(ProtoCiphertext {
+            version: PROTO_VERSION,
+            scheme,
+            params_hash,
+            data_type,
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// The metadata fields of a message, as stored.
+fn metadata_fields(metadata: &FheMetadata) -> (String, Vec<u8>, String) {
+    let scheme = match metadata.scheme {
+        SchemeType::Bfv => "bfv",
+    };
+    let data_type = format!(
+        "{},{},{}",
+        metadata.data_type.name, metadata.data_type.version, metadata.data_type.is_encrypted
+    );
+    (scheme.to_owned(), metadata.params_hash.to_vec(), data_type)
+}
+
+fn metadata_fields_of(proto: &ProtoCiphertext) -> (String, Vec<u8>, String) {
+    (
+        proto.scheme.clone(),
+        proto.params_hash.clone(),
+        proto.data_type.clone(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::{tests::runtime, Unsigned256};
+
+    #[test]
+    fn proto_roundtrip() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(5), &public_key).unwrap();
+
+        let proto = ciphertext.to_proto().unwrap();
+        assert_eq!(proto.scheme, "bfv");
+        let decoded = ProtoCiphertext::decode(proto.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, proto);
+
+        let ciphertext = Ciphertext::from_proto(&decoded).unwrap();
+        let value: Unsigned256 = runtime.decrypt(&ciphertext, &private_key).unwrap();
+        assert_eq!(value, Unsigned256::from(5));
+
+        let future = ProtoCiphertext {
+            version: PROTO_VERSION + 1,
+            ..proto.clone()
+        };
+        assert!(matches!(
+            Ciphertext::from_proto(&future),
+            Err(Error::Version { .. })
+        ));
+
+        let tampered = ProtoCiphertext {
+            params_hash: vec![0; 32],
+            ..proto
+        };
+        assert!(Ciphertext::from_proto(&tampered).is_err());
+    }
+}
@@ -0,0 +1,382 @@
+//! This module offers WebSocket log subscriptions which survive brief network interruptions, e.g.
+//! to keep an FHE event listener alive. It is only available with the `ws` feature.
+
+use std::time::Duration;
+
+use ethers::{
+    providers::{Middleware, Provider, StreamExt, Ws},
+    types::{BlockNumber, Filter, FilterBlockOption, Log, U256, U64},
+};
+use futures_util::{
+    future::{self, Either},
+    stream, Stream,
+};
+use tokio::sync::mpsc;
+
+use super::{tx::middleware_error, Error, Result};
+
+/// Controls how [`ReconnectingWs`] retries after losing its connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectOptions {
+    /// Give up after this many consecutive failed attempts to connect and subscribe.
+    pub max_attempts: usize,
+    /// How long to wait before the first retry. The wait doubles after each failed attempt.
+    pub initial_backoff: Duration,
+    /// The longest wait between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectOptions {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A WebSocket endpoint whose subscriptions reconnect and resubscribe whenever the connection
+/// drops, rather than silently ending.
+///
+/// The ethers WebSocket transport already reconnects a few times on its own, but gives up for good
+/// if the node cannot be reached at the moment it retries. This retries with exponential backoff
+/// instead, and fetches the logs emitted while disconnected so that none are missed.
+#[derive(Debug, Clone)]
+pub struct ReconnectingWs {
+    url: String,
+    options: ReconnectOptions,
+}
+
+/// How a connection to the node ended.
+enum Session {
+    /// The caller dropped the stream, so there is nothing left to do.
+    Stopped,
+    /// The connection dropped after subscribing successfully.
+    Disconnected,
+    /// Connecting or subscribing failed.
+    Failed(Error),
+}
+
+/// How many logs are buffered for a slow consumer before reading from the node pauses.
+const LOG_BUFFER: usize = 64;
+
+impl ReconnectingWs {
+    /// Construct a reconnecting endpoint for the WebSocket `url`, e.g. `ws://localhost:8545`. No
+    /// connection is made until a subscription is started.
+    pub fn new(url: impl Into<String>, options: ReconnectOptions) -> Self {
+        Self {
+            url: url.into(),
+            options,
+        }
+    }
+
+    /// Subscribe to logs matching `filter`, yielding them as one continuous stream across
+    /// reconnections. Logs are yielded at most once, in the order they were emitted, starting with
+    /// those of the latest block when the subscription is first made.
+    ///
+    /// If the node cannot be reached within [`ReconnectOptions::max_attempts`] consecutive
+    /// attempts, the stream yields the last error and ends. The subscription runs in a background
+    /// task, which stops once the stream is dropped.
+    pub fn subscribe_logs(&self, filter: Filter) -> impl Stream<Item = Result<Log>> {
+        let (sender, receiver) = mpsc::channel(LOG_BUFFER);
+        tokio::spawn(self.clone().run(filter, sender));
+        stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        })
+    }
+
+    async fn run(self, filter: Filter, sender: mpsc::Sender<Result<Log>>) {
+        let mut cursor = Cursor::default();
+        let mut failures = 0;
+        let mut backoff = self.options.initial_backoff;
+        loop {
+            match self.session(&filter, &mut cursor, &sender).await {
+                Session::Stopped => return,
+                Session::Disconnected => {
+                    failures = 0;
+                    backoff = self.options.initial_backoff;
+                }
+                Session::Failed(e) => {
+                    failures += 1;
+                    if failures >= self.options.max_attempts {
+                        let _ = sender.send(Err(e)).await;
+                        return;
+                    }
+                }
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.options.max_backoff);
+        }
+    }
+
+    /// Connect, subscribe, catch up on logs missed since the previous session, and forward logs
+    /// until the connection drops.
+    async fn session(
+        &self,
+        filter: &Filter,
+        cursor: &mut Cursor,
+        sender: &mpsc::Sender<Result<Log>>,
+    ) -> Session {
+        let provider = match Provider::<Ws>::connect(&self.url).await {
+            Ok(provider) => provider,
+            Err(e) => return Session::Failed(middleware_error(e)),
+        };
+        let mut logs = match provider.subscribe_logs(filter).await {
+            Ok(logs) => logs,
+            Err(e) => return Session::Failed(middleware_error(e)),
+        };
+        // Only read the head once subscribed, so that every block after it is streamed live.
+        let head = match provider.get_block_number().await {
+            Ok(head) => head,
+            Err(e) => return Session::Failed(middleware_error(e)),
+        };
+
+        // Blocks up to the head may have been mined before the subscription took effect, so their
+        // logs are fetched, on the first session too. Logs streamed live meanwhile are buffered by
+        // the subscription, and those also fetched here are skipped when they arrive.
+        let from_block = cursor.resume_from(head);
+        if let Some(catch_up) = catch_up_filter(filter, from_block) {
+            let missed = match provider.get_logs(&catch_up).await {
+                Ok(missed) => missed,
+                Err(e) => return Session::Failed(middleware_error(e)),
+            };
+            for log in missed {
+                if !cursor.deliver(log, sender).await {
+                    return Session::Stopped;
+                }
+            }
+        }
+
+        // Stop as soon as the stream is dropped, rather than when the next log arrives.
+        let closed = sender.closed();
+        futures_util::pin_mut!(closed);
+        loop {
+            let log = match future::select(logs.next(), closed.as_mut()).await {
+                Either::Left((Some(log), _)) => log,
+                Either::Left((None, _)) => return Session::Disconnected,
+                Either::Right(_) => return Session::Stopped,
+            };
+            if !cursor.deliver(log, sender).await {
+                return Session::Stopped;
+            }
+        }
+    }
+}
+
+/// Narrow `filter` to the logs from `resume_from` onwards, to fetch those missed while
+/// disconnected. The filter's own block range is kept where it is narrower, and `None` is returned
+/// if no logs within it could have been missed, including for filters of a single block hash.
+fn catch_up_filter(filter: &Filter, resume_from: U64) -> Option<Filter> {
+    let to_block = match filter.block_option {
+        FilterBlockOption::AtBlockHash(_) => return None,
+        FilterBlockOption::Range { to_block, .. } => to_block,
+    };
+    let from_block = match filter.get_from_block() {
+        Some(from_block) => from_block.max(resume_from),
+        None => resume_from,
+    };
+    if let Some(BlockNumber::Number(to_block)) = to_block {
+        if to_block < from_block {
+            return None;
+        }
+    }
+    let mut catch_up = filter.clone();
+    catch_up.block_option = FilterBlockOption::Range {
+        from_block: Some(from_block.into()),
+        to_block,
+    };
+    Some(catch_up)
+}
+
+/// Tracks which logs have been yielded, to catch up after reconnecting without repeating any.
+#[derive(Default)]
+struct Cursor {
+    /// The block the stream starts at, the latest block when the first subscription was made.
+    start: Option<U64>,
+    /// The block number and log index of the last log yielded.
+    last: Option<(U64, U256)>,
+}
+
+impl Cursor {
+    /// The first block which may contain logs not yet yielded, given the latest block `head` of a
+    /// new subscription. The stream starts at the head of the first subscription.
+    fn resume_from(&mut self, head: U64) -> U64 {
+        let start = *self.start.get_or_insert(head);
+        match self.last {
+            Some((block, _)) => block,
+            None => start,
+        }
+    }
+
+    /// Yield a log unless it was already yielded. Returns false if the stream was dropped.
+    async fn deliver(&mut self, log: Log, sender: &mpsc::Sender<Result<Log>>) -> bool {
+        if let (Some(block), Some(index)) = (log.block_number, log.log_index) {
+            if self.last.is_some_and(|last| (block, index) <= last) {
+                return true;
+            }
+            self.last = Some((block, index));
+        }
+        sender.send(Ok(log)).await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        signers::Signer,
+        types::{Address, Bytes, TransactionRequest, H256},
+    };
+
+    use super::*;
+    use crate::testing::{Node, ALICE};
+
+    #[tokio::test]
+    async fn streams_logs_and_skips_duplicates() {
+        let node = Node::default();
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+
+        // Runtime code which emits an empty LOG0.
+        let emitter = Address::repeat_byte(0x52);
+        provider
+            .request::<_, ()>(
+                "anvil_setCode",
+                (
+                    emitter,
+                    Bytes::from(vec![0x60, 0x00, 0x60, 0x00, 0xa0, 0x00]),
+                ),
+            )
+            .await
+            .unwrap();
+
+        let ws = ReconnectingWs::new(
+            node.anvil.as_ref().unwrap().ws_endpoint(),
+            ReconnectOptions::default(),
+        );
+        let logs = ws.subscribe_logs(Filter::new().address(emitter));
+        futures_util::pin_mut!(logs);
+        // Give the subscription time to be established.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        for _ in 0..2 {
+            let tx = TransactionRequest::new().to(emitter).from(ALICE.address());
+            client
+                .send_transaction(tx, None)
+                .await
+                .unwrap()
+                .await
+                .unwrap();
+        }
+        let first = logs.next().await.unwrap().unwrap();
+        let second = logs.next().await.unwrap().unwrap();
+        assert_eq!(first.address, emitter);
+        assert!(second.block_number > first.block_number);
+
+        let mut cursor = Cursor {
+            start: None,
+            last: Some((second.block_number.unwrap(), second.log_index.unwrap())),
+        };
+        let (sender, mut receiver) = mpsc::channel(1);
+        assert!(cursor.deliver(first, &sender).await);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn streams_logs_mined_while_subscribing() {
+        let node = Node::default();
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+
+        // Runtime code which emits an empty LOG0.
+        let emitter = Address::repeat_byte(0x53);
+        provider
+            .request::<_, ()>(
+                "anvil_setCode",
+                (
+                    emitter,
+                    Bytes::from(vec![0x60, 0x00, 0x60, 0x00, 0xa0, 0x00]),
+                ),
+            )
+            .await
+            .unwrap();
+
+        let ws = ReconnectingWs::new(
+            node.anvil.as_ref().unwrap().ws_endpoint(),
+            ReconnectOptions::default(),
+        );
+        let logs = ws.subscribe_logs(Filter::new().address(emitter));
+        futures_util::pin_mut!(logs);
+        // Emit a log without waiting for the subscription to be established.
+        let tx = TransactionRequest::new().to(emitter).from(ALICE.address());
+        let receipt = client
+            .send_transaction(tx, None)
+            .await
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+
+        let log = tokio::time::timeout(Duration::from_secs(5), logs.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(log.transaction_hash, Some(receipt.transaction_hash));
+    }
+
+    #[test]
+    fn cursor_resumes_after_last_log() {
+        let mut cursor = Cursor::default();
+        assert_eq!(cursor.resume_from(5.into()), U64::from(5));
+        // Later heads do not move the start of the stream.
+        assert_eq!(cursor.resume_from(8.into()), U64::from(5));
+        cursor.last = Some((7.into(), U256::zero()));
+        assert_eq!(cursor.resume_from(9.into()), U64::from(7));
+    }
+
+    #[test]
+    fn catch_up_keeps_filter_bounds() {
+        let resume = U64::from(10);
+        let range = |filter: Filter| match catch_up_filter(&filter, resume).unwrap().block_option {
+            FilterBlockOption::Range {
+                from_block,
+                to_block,
+            } => (from_block, to_block),
+            FilterBlockOption::AtBlockHash(_) => panic!("expected a block range"),
+        };
+
+        assert_eq!(range(Filter::new()), (Some(resume.into()), None));
+        assert_eq!(
+            range(Filter::new().from_block(5).to_block(20)),
+            (Some(resume.into()), Some(20.into()))
+        );
+        assert_eq!(
+            range(Filter::new().from_block(15).to_block(BlockNumber::Latest)),
+            (Some(15.into()), Some(BlockNumber::Latest))
+        );
+
+        assert!(catch_up_filter(&Filter::new().from_block(1).to_block(9), resume).is_none());
+        assert!(catch_up_filter(&Filter::new().at_block_hash(H256::zero()), resume).is_none());
+    }
+
+    #[tokio::test]
+    async fn surfaces_unreachable_node() {
+        let node = Node::default();
+        let url = node.anvil.as_ref().unwrap().ws_endpoint();
+        node.shutdown().unwrap();
+
+        let ws = ReconnectingWs::new(
+            url,
+            ReconnectOptions {
+                max_attempts: 2,
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+            },
+        );
+        let logs = ws.subscribe_logs(Filter::new());
+        futures_util::pin_mut!(logs);
+        assert!(logs.next().await.unwrap().is_err());
+        assert!(logs.next().await.is_none());
+    }
+}
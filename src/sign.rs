@@ -0,0 +1,328 @@
+//! This module offers helpers for producing off-chain signatures, e.g. to authorize FHE
+//! operations without submitting a transaction, and for checking who signed a transaction.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use ethers::{
+    core::k256::ecdsa::VerifyingKey,
+    signers::{coins_bip39::English, LocalWallet, MnemonicBuilder, Signer},
+    types::{
+        transaction::{
+            eip2718::TypedTransaction,
+            eip712::{EIP712Domain, Eip712DomainType, TypedData, Types},
+        },
+        Address, Bytes, Signature, U256,
+    },
+    utils::rlp::Rlp,
+};
+
+use super::Result;
+
+/// Sign EIP-712 structured data, producing a signature that a Solidity contract can verify with
+/// `ecrecover`.
+///
+/// The domain's chain id is always set from the wallet, so make sure the wallet is configured for
+/// the target network (e.g. via [`crate::testnet::TestnetProvider::client`] or
+/// [`Signer::with_chain_id`]).
+pub async fn sign_typed_data(
+    wallet: &LocalWallet,
+    mut domain: EIP712Domain,
+    types: Types,
+    primary_type: &str,
+    value: BTreeMap<String, serde_json::Value>,
+) -> Result<Signature> {
+    domain.chain_id = Some(wallet.chain_id().into());
+    let typed_data = TypedData {
+        domain,
+        types,
+        primary_type: primary_type.to_owned(),
+        message: value,
+    };
+    let signature = wallet.sign_typed_data(&typed_data).await?;
+    Ok(signature)
+}
+
+/// The EIP-712 domain of an [EIP-2612](https://eips.ethereum.org/EIPS/eip-2612) permit-enabled
+/// token, for [`sign_permit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermitToken {
+    /// The address of the token contract.
+    pub address: Address,
+    /// The token's EIP-712 domain name, usually the token's `name()`.
+    pub name: String,
+    /// The token's EIP-712 domain version, usually `"1"`.
+    pub version: String,
+}
+
+/// Sign an EIP-2612 permit allowing `spender` to spend `value` of the wallet's tokens, e.g. so a
+/// user can approve an FHE token contract without paying gas. Submit the signature's `v`, `r` and
+/// `s` to the token's `permit(owner, spender, value, deadline, v, r, s)` before `deadline`.
+///
+/// `nonce` must be the owner's current `nonces(owner)` on the token. Unlike [`sign_typed_data`],
+/// the domain's chain id is `chain_id` rather than the wallet's.
+pub async fn sign_permit(
+    wallet: &LocalWallet,
+    token: &PermitToken,
+    spender: Address,
+    value: U256,
+    deadline: U256,
+    nonce: U256,
+    chain_id: u64,
+) -> Result<Signature> {
+    let field = |name: &str, r#type: &str| Eip712DomainType {
+        name: name.to_owned(),
+        r#type: r#type.to_owned(),
+    };
+    let typed_data = TypedData {
+        domain: EIP712Domain {
+            name: Some(token.name.clone()),
+            version: Some(token.version.clone()),
+            chain_id: Some(chain_id.into()),
+            verifying_contract: Some(token.address),
+            salt: None,
+        },
+        types: Types::from([(
+            "Permit".to_owned(),
+            vec![
+                field("owner", "address"),
+                field("spender", "address"),
+                field("value", "uint256"),
+                field("nonce", "uint256"),
+                field("deadline", "uint256"),
+            ],
+        )]),
+        primary_type: "Permit".to_owned(),
+        message: BTreeMap::from([
+            ("owner".to_owned(), serde_json::json!(wallet.address())),
+            ("spender".to_owned(), serde_json::json!(spender)),
+            ("value".to_owned(), serde_json::json!(value)),
+            ("nonce".to_owned(), serde_json::json!(nonce)),
+            ("deadline".to_owned(), serde_json::json!(deadline)),
+        ]),
+    };
+    Ok(wallet.sign_typed_data(&typed_data).await?)
+}
+
+/// The secp256k1 public key of a wallet, e.g. to register a signing identity alongside an FHE
+/// public key. Use [`VerifyingKey::to_encoded_point`] to encode it as bytes, and
+/// [`ethers::utils::public_key_to_address`] to derive the wallet's address from it.
+pub fn wallet_public_key(wallet: &LocalWallet) -> VerifyingKey {
+    *wallet.signer().verifying_key()
+}
+
+/// A set of wallets derived from one mnemonic, e.g. to manage many user accounts from a single
+/// seed. Wallets are derived on demand along the standard Ethereum path `m/44'/60'/0'/0/{index}`,
+/// and cached, since derivation is comparatively expensive.
+///
+/// Pass a derived wallet to e.g. [`crate::testnet::TestnetProvider::client`] to build
+/// [`crate::SignedMiddleware`] for any index.
+pub struct HdWallet {
+    phrase: String,
+    signers: Mutex<HashMap<u32, LocalWallet>>,
+}
+
+impl HdWallet {
+    /// Construct a wallet set from a BIP-39 English mnemonic. Returns an error if the mnemonic is
+    /// invalid.
+    pub fn new(phrase: &str) -> Result<Self> {
+        let wallet = Self {
+            phrase: phrase.to_owned(),
+            signers: Mutex::new(HashMap::new()),
+        };
+        wallet.signer(0)?;
+        Ok(wallet)
+    }
+
+    /// The wallet at `index`.
+    pub fn signer(&self, index: u32) -> Result<LocalWallet> {
+        let mut signers = self.signers.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(signer) = signers.get(&index) {
+            return Ok(signer.clone());
+        }
+        let signer = MnemonicBuilder::<English>::default()
+            .phrase(self.phrase.as_str())
+            .index(index)?
+            .build()?;
+        signers.insert(index, signer.clone());
+        Ok(signer)
+    }
+}
+
+impl std::fmt::Debug for HdWallet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HdWallet").finish_non_exhaustive()
+    }
+}
+
+/// Recover the address that signed a raw, RLP-encoded transaction, e.g. to check that a
+/// transaction submitted through a relayer was signed by the expected account. Both legacy and
+/// typed (EIP-2718) transactions are supported.
+pub fn recover_sender(raw: &Bytes) -> Result<Address> {
+    let (tx, signature) = TypedTransaction::decode_signed(&Rlp::new(raw))
+        .map_err(|e| anyhow::anyhow!("malformed signed transaction: {}", e))?;
+    let sender = signature
+        .recover(tx.sighash())
+        .map_err(|e| anyhow::anyhow!("invalid transaction signature: {}", e))?;
+    Ok(sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{self, Token},
+        types::{transaction::eip712::Eip712, Eip1559TransactionRequest, TransactionRequest},
+        utils::keccak256,
+    };
+
+    use super::*;
+    use crate::testing::{ALICE, BOB};
+
+    #[tokio::test]
+    async fn typed_data_signature_recovers_signer() {
+        let wallet = ALICE.clone().with_chain_id(574u64);
+        let domain = EIP712Domain {
+            name: Some("EncryptedToken".to_owned()),
+            version: Some("1".to_owned()),
+            chain_id: None,
+            verifying_contract: Some(BOB.address()),
+            salt: None,
+        };
+        let types = Types::from([(
+            "Transfer".to_owned(),
+            vec![
+                Eip712DomainType {
+                    name: "to".to_owned(),
+                    r#type: "address".to_owned(),
+                },
+                Eip712DomainType {
+                    name: "amount".to_owned(),
+                    r#type: "bytes".to_owned(),
+                },
+            ],
+        )]);
+        let value = BTreeMap::from([
+            (
+                "to".to_owned(),
+                serde_json::json!(format!("{:?}", BOB.address())),
+            ),
+            ("amount".to_owned(), serde_json::json!("0x1234")),
+        ]);
+
+        let signature = sign_typed_data(
+            &wallet,
+            domain.clone(),
+            types.clone(),
+            "Transfer",
+            value.clone(),
+        )
+        .await
+        .unwrap();
+
+        let typed_data = TypedData {
+            domain: EIP712Domain {
+                chain_id: Some(574u64.into()),
+                ..domain
+            },
+            types,
+            primary_type: "Transfer".to_owned(),
+            message: value,
+        };
+        let hash = typed_data.encode_eip712().unwrap();
+        assert_eq!(signature.recover(hash).unwrap(), ALICE.address());
+    }
+
+    #[tokio::test]
+    async fn permit_matches_eip2612_digest() {
+        let token = PermitToken {
+            address: Address::repeat_byte(0x33),
+            name: "EncryptedToken".to_owned(),
+            version: "1".to_owned(),
+        };
+        let (value, deadline, nonce) = (U256::exp10(18), U256::from(1_700_000_000u64), 4.into());
+        let signature = sign_permit(&ALICE, &token, BOB.address(), value, deadline, nonce, 574)
+            .await
+            .unwrap();
+
+        // The digest as computed by OpenZeppelin's ERC20Permit.
+        let type_hash = |signature: &str| Token::FixedBytes(keccak256(signature).to_vec());
+        let domain_separator = keccak256(abi::encode(&[
+            type_hash(
+                "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)",
+            ),
+            Token::FixedBytes(keccak256("EncryptedToken").to_vec()),
+            Token::FixedBytes(keccak256("1").to_vec()),
+            Token::Uint(574.into()),
+            Token::Address(token.address),
+        ]));
+        let struct_hash = keccak256(abi::encode(&[
+            type_hash(
+                "Permit(address owner,address spender,uint256 value,uint256 nonce,uint256 deadline)",
+            ),
+            Token::Address(ALICE.address()),
+            Token::Address(BOB.address()),
+            Token::Uint(value),
+            Token::Uint(nonce),
+            Token::Uint(deadline),
+        ]));
+        let digest = keccak256([&[0x19, 0x01][..], &domain_separator, &struct_hash].concat());
+        assert_eq!(signature.recover(digest).unwrap(), ALICE.address());
+
+        let other_chain = sign_permit(&ALICE, &token, BOB.address(), value, deadline, nonce, 1)
+            .await
+            .unwrap();
+        assert_ne!(other_chain.recover(digest).unwrap(), ALICE.address());
+    }
+
+    #[tokio::test]
+    async fn recovers_transaction_sender() {
+        let wallet = ALICE.clone().with_chain_id(574u64);
+        let txs: [TypedTransaction; 2] = [
+            TransactionRequest::new()
+                .to(BOB.address())
+                .value(1)
+                .nonce(0)
+                .gas(21_000)
+                .gas_price(1)
+                .chain_id(574u64)
+                .into(),
+            Eip1559TransactionRequest::new()
+                .to(BOB.address())
+                .value(1)
+                .nonce(0)
+                .gas(21_000)
+                .max_fee_per_gas(2)
+                .max_priority_fee_per_gas(1)
+                .chain_id(574u64)
+                .into(),
+        ];
+        for tx in txs {
+            let signature = wallet.sign_transaction(&tx).await.unwrap();
+            let raw = tx.rlp_signed(&signature);
+            assert_eq!(recover_sender(&raw).unwrap(), ALICE.address());
+        }
+
+        assert!(recover_sender(&Bytes::from(vec![0xde, 0xad])).is_err());
+    }
+
+    #[test]
+    fn wallet_public_key_matches_address() {
+        let key = wallet_public_key(&ALICE);
+        assert_eq!(ethers::utils::public_key_to_address(&key), ALICE.address());
+        assert_eq!(key.to_encoded_point(false).as_bytes().len(), 65);
+        assert_ne!(key, wallet_public_key(&BOB));
+    }
+
+    #[test]
+    fn hd_wallet_derives_indexed_signers() {
+        let wallet = HdWallet::new(crate::testing::ANVIL_MNEMONIC).unwrap();
+        assert_eq!(wallet.signer(0).unwrap().address(), ALICE.address());
+        assert_eq!(wallet.signer(1).unwrap().address(), BOB.address());
+        assert_eq!(wallet.signer(1).unwrap().address(), BOB.address());
+        assert!(!format!("{:?}", wallet).contains("gas"));
+
+        assert!(HdWallet::new("not a mnemonic").is_err());
+    }
+}
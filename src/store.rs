@@ -0,0 +1,492 @@
+//! This module offers helpers for persisting FHE values in the filesystem.
+
+use std::path::{Path, PathBuf};
+
+use eth_keystore::KeystoreError;
+use ethers::{
+    core::rand::{thread_rng, RngCore},
+    signers::LocalWallet,
+    types::Bytes,
+};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    testnet::TestnetProvider, AsBytes, Bincode, Ciphertext, Codec, Error, PrivateKey, PublicKey,
+    Result,
+};
+
+/// Load every ciphertext in a directory whose files are numbered, e.g. `0`, `1`, ... or
+/// `0.bin`, `1.bin`, ..., in numeric order (so `10` comes after `9`). Subdirectories are ignored.
+///
+/// If `strict` is false, files without a numeric name or which do not contain a ciphertext are
+/// skipped. If it is true, such files are reported as errors instead.
+pub fn load_ciphertext_dir<P: AsRef<Path>>(path: P, strict: bool) -> Result<Vec<Ciphertext>> {
+    let mut numbered = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        match file_number(&path) {
+            Some(n) => numbered.push((n, path)),
+            None if strict => {
+                return Err(
+                    anyhow::anyhow!("{} is not a numbered ciphertext file", path.display()).into(),
+                )
+            }
+            None => {}
+        }
+    }
+    numbered.sort_by_key(|(n, _)| *n);
+
+    let mut ciphertexts = Vec::with_capacity(numbered.len());
+    for (_, path) in numbered {
+        // Decode from memory rather than streaming from the file, as bincode then bounds any
+        // length prefixes by the data actually present.
+        let bytes = Bytes::from(std::fs::read(&path)?);
+        match Ciphertext::from_bytes(&bytes) {
+            Ok(ciphertext) => ciphertexts.push(ciphertext),
+            Err(Error::Conversion(_)) if !strict => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(ciphertexts)
+}
+
+/// Archive all but the `keep` highest numbered ciphertext files of a directory like those read by
+/// [`load_ciphertext_dir`], deleting the archived files, e.g. to keep a cache from growing without
+/// bound. Files without a numeric name are left alone.
+///
+/// The archive is written next to the directory, with an `archive` extension, and its path is
+/// returned. If it already exists, the newly archived ciphertexts are appended to it, so repeated
+/// compaction keeps every ciphertext in order. Read it with [`load_ciphertext_archive`].
+pub fn compact_ciphertext_dir<P: AsRef<Path>>(path: P, keep: usize) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let archive = path.with_extension("archive");
+
+    let mut numbered = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if let (true, Some(n)) = (path.is_file(), file_number(&path)) {
+            numbered.push((n, path));
+        }
+    }
+    numbered.sort_by_key(|(n, _)| *n);
+    let old = &numbered[..numbered.len().saturating_sub(keep)];
+
+    let mut ciphertexts = if archive.exists() {
+        load_ciphertext_archive(&archive)?
+    } else {
+        Vec::new()
+    };
+    for (_, path) in old {
+        ciphertexts.push(Ciphertext::from_bytes(&std::fs::read(path)?.into())?);
+    }
+
+    // Only delete the loose files once the archive holding them is safely written.
+    let partial = archive.with_extension("archive.partial");
+    Bincode::encode_into(std::fs::File::create(&partial)?, &ciphertexts)?;
+    std::fs::rename(&partial, &archive)?;
+    for (_, path) in old {
+        std::fs::remove_file(path)?;
+    }
+    Ok(archive)
+}
+
+/// Read the ciphertexts archived by [`compact_ciphertext_dir`], oldest first.
+pub fn load_ciphertext_archive<P: AsRef<Path>>(path: P) -> Result<Vec<Ciphertext>> {
+    Bincode::decode(&std::fs::read(path)?)
+}
+
+/// The number a file is named after, ignoring any extension.
+fn file_number(path: &Path) -> Option<u64> {
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// The network settings stored in a bundle by [`export_bundle`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    pub rpc_url: String,
+    pub chain_id: u64,
+    pub faucet_url: String,
+}
+
+impl From<&TestnetProvider> for NetworkConfig {
+    fn from(testnet: &TestnetProvider) -> Self {
+        Self {
+            rpc_url: testnet.rpc_url.to_owned(),
+            chain_id: testnet.chain_id,
+            faucet_url: testnet.faucet_url.to_owned(),
+        }
+    }
+}
+
+/// Write a keypair and network settings to a single passphrase-protected file, e.g. to back up
+/// an FHE identity. The file uses the Ethereum keystore format (scrypt and AES-128-CTR).
+///
+/// Restore it with [`import_bundle`].
+pub fn export_bundle<P: AsRef<Path>>(
+    path: P,
+    passphrase: &str,
+    keys: &(PublicKey, PrivateKey),
+    config: &NetworkConfig,
+) -> Result<()> {
+    let path = path.as_ref();
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("{} is not a file path", path.display()))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let contents = bincode::serialize(&(keys, config))?;
+    eth_keystore::encrypt_key(dir, &mut thread_rng(), contents, passphrase, Some(name))
+        .map_err(keystore_error)?;
+    Ok(())
+}
+
+/// Read a keypair and network settings written by [`export_bundle`].
+///
+/// Returns [`Error::Passphrase`] if `passphrase` is not the one the bundle was exported with.
+pub fn import_bundle<P: AsRef<Path>>(
+    path: P,
+    passphrase: &str,
+) -> Result<((PublicKey, PrivateKey), NetworkConfig)> {
+    let contents = eth_keystore::decrypt_key(path, passphrase).map_err(keystore_error)?;
+    Ok(bincode::deserialize(&contents)?)
+}
+
+/// The HKDF salt for [`derive_storage_key`], separating its keys from any other use of the wallet's
+/// secret.
+const STORAGE_KEY_SALT: &[u8] = b"sunscreen_web3 storage key";
+
+/// Derive a symmetric key from a wallet's secret, e.g. to encrypt local caches of ciphertexts
+/// without asking the user for a separate passphrase. The key is derived with HKDF-SHA256, using
+/// `context` as the info string, so the same wallet and context always produce the same key while
+/// different contexts produce unrelated keys.
+///
+/// Anyone holding the wallet can derive the key, so it is only as secret as the wallet itself.
+pub fn derive_storage_key(wallet: &LocalWallet, context: &str) -> [u8; 32] {
+    let secret = wallet.signer().to_bytes();
+    let hkdf = Hkdf::<Sha256>::new(Some(STORAGE_KEY_SALT), &secret);
+    let mut key = [0; 32];
+    hkdf.expand(context.as_bytes(), &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+/// One share of a private key split by [`split_private_key`]. Each share reveals nothing about the
+/// key on its own, but must still be stored as carefully as the key, since enough of them
+/// reconstruct it.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Share {
+    /// The point at which this share evaluates the sharing polynomials, from 1 to the number of
+    /// shares.
+    pub index: u8,
+    /// How many shares are needed to reconstruct the key.
+    pub threshold: u8,
+    /// One byte per byte of the shared secret.
+    pub data: Vec<u8>,
+}
+
+impl std::fmt::Debug for Share {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Share")
+            .field("index", &self.index)
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Split a private key into `shares` shares using Shamir secret sharing over GF(256), such that
+/// any `threshold` of them reconstruct it with [`reconstruct_private_key`], e.g. to distribute
+/// custody of a decryption key across operators.
+pub fn split_private_key(key: &PrivateKey, threshold: u8, shares: u8) -> Result<Vec<Share>> {
+    if threshold == 0 || threshold > shares {
+        return Err(anyhow::anyhow!(
+            "cannot split into {} shares with a threshold of {}",
+            shares,
+            threshold
+        )
+        .into());
+    }
+    // Share a checksum along with the key, so that reconstructing from mismatched or corrupted
+    // shares fails rather than producing garbage.
+    let mut secret = key.as_bytes()?.to_vec();
+    let checksum = Sha256::digest(&secret);
+    secret.extend_from_slice(&checksum);
+
+    let mut rng = thread_rng();
+    let mut split = (1..=shares)
+        .map(|index| Share {
+            index,
+            threshold,
+            data: Vec::with_capacity(secret.len()),
+        })
+        .collect::<Vec<_>>();
+    let mut coefficients = vec![0; threshold as usize];
+    for byte in secret {
+        coefficients[0] = byte;
+        rng.fill_bytes(&mut coefficients[1..]);
+        for share in &mut split {
+            // Horner's method, from the highest degree coefficient down.
+            let y = coefficients
+                .iter()
+                .rev()
+                .fold(0, |y, c| gf256_mul(y, share.index) ^ c);
+            share.data.push(y);
+        }
+    }
+    Ok(split)
+}
+
+/// Reconstruct a private key from shares produced by [`split_private_key`]. Fails if there are
+/// fewer shares than the threshold they were split with, or if the shares do not belong together.
+pub fn reconstruct_private_key(shares: &[Share]) -> Result<PrivateKey> {
+    let threshold = match shares.first() {
+        Some(share) => share.threshold,
+        None => return Err(anyhow::anyhow!("no shares to reconstruct from").into()),
+    };
+    if shares.len() < threshold as usize {
+        return Err(anyhow::anyhow!(
+            "{} shares cannot reconstruct a key with a threshold of {}",
+            shares.len(),
+            threshold
+        )
+        .into());
+    }
+    let shares = &shares[..threshold as usize];
+    for (i, share) in shares.iter().enumerate() {
+        if share.threshold != threshold || share.data.len() != shares[0].data.len() {
+            return Err(anyhow::anyhow!("shares were not split from the same key").into());
+        }
+        if share.index == 0 || shares[..i].iter().any(|other| other.index == share.index) {
+            return Err(anyhow::anyhow!("invalid or duplicate share index {}", share.index).into());
+        }
+    }
+
+    // Lagrange interpolation at zero. Subtraction in GF(256) is XOR.
+    let weights = shares
+        .iter()
+        .map(|share| {
+            shares
+                .iter()
+                .filter(|other| other.index != share.index)
+                .fold(1, |weight, other| {
+                    gf256_mul(
+                        weight,
+                        gf256_mul(other.index, gf256_inv(other.index ^ share.index)),
+                    )
+                })
+        })
+        .collect::<Vec<_>>();
+    let mut secret = (0..shares[0].data.len())
+        .map(|i| {
+            shares
+                .iter()
+                .zip(&weights)
+                .fold(0, |byte, (share, weight)| {
+                    byte ^ gf256_mul(share.data[i], *weight)
+                })
+        })
+        .collect::<Vec<_>>();
+
+    if secret.len() < 32 {
+        return Err(anyhow::anyhow!("shares are too short").into());
+    }
+    let checksum = secret.split_off(secret.len() - 32);
+    if Sha256::digest(&secret).as_slice() != checksum.as_slice() {
+        return Err(anyhow::anyhow!("shares were not split from the same key").into());
+    }
+    PrivateKey::from_bytes(&secret.into())
+}
+
+/// Multiply in GF(256) with the AES reduction polynomial.
+fn gf256_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+/// Invert a nonzero element of GF(256), as `a^254`.
+fn gf256_inv(a: u8) -> u8 {
+    let mut result = 1;
+    let mut power = a;
+    let mut exponent = 254u8;
+    while exponent != 0 {
+        if exponent & 1 != 0 {
+            result = gf256_mul(result, power);
+        }
+        power = gf256_mul(power, power);
+        exponent >>= 1;
+    }
+    result
+}
+
+fn keystore_error(e: KeystoreError) -> Error {
+    match e {
+        KeystoreError::MacMismatch => Error::Passphrase,
+        KeystoreError::StdIo(e) => Error::Io(std::io::Error::other(e)),
+        e => anyhow::Error::new(e).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testnet::parasol::PARASOL, tests::runtime, AsFile, Unsigned256};
+
+    #[test]
+    fn gf256_inverts() {
+        for a in 1..=255u8 {
+            assert_eq!(gf256_mul(a, gf256_inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn reconstructs_private_key_from_threshold_shares() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime.encrypt(Unsigned256::from(8), &public_key).unwrap();
+
+        let shares = split_private_key(&private_key, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        let subset = [shares[4].clone(), shares[1].clone(), shares[2].clone()];
+        let reconstructed = reconstruct_private_key(&subset).unwrap();
+        let value: Unsigned256 = runtime.decrypt(&ciphertext, &reconstructed).unwrap();
+        assert_eq!(value, Unsigned256::from(8));
+        assert!(reconstruct_private_key(&shares).is_ok());
+
+        assert!(reconstruct_private_key(&shares[..2]).is_err());
+        assert!(reconstruct_private_key(&[
+            shares[0].clone(),
+            shares[0].clone(),
+            shares[1].clone()
+        ])
+        .is_err());
+        let mut corrupted = subset.clone();
+        corrupted[0].data[0] ^= 1;
+        assert!(reconstruct_private_key(&corrupted).is_err());
+        let other = split_private_key(&private_key, 3, 5).unwrap();
+        assert!(
+            reconstruct_private_key(&[shares[0].clone(), shares[1].clone(), other[2].clone()])
+                .is_err()
+        );
+
+        assert!(split_private_key(&private_key, 0, 5).is_err());
+        assert!(split_private_key(&private_key, 6, 5).is_err());
+    }
+
+    #[test]
+    fn loads_numbered_ciphertexts_in_order() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        for n in [2u64, 10, 1] {
+            let ciphertext = runtime.encrypt(Unsigned256::from(n), &public_key).unwrap();
+            ciphertext
+                .write(dir.path().join(format!("{}.bin", n)))
+                .unwrap();
+        }
+        std::fs::write(dir.path().join("notes.txt"), "not a ciphertext").unwrap();
+        std::fs::write(dir.path().join("3"), "not a ciphertext either").unwrap();
+
+        let ciphertexts = load_ciphertext_dir(dir.path(), false).unwrap();
+        let values = ciphertexts
+            .iter()
+            .map(|c| runtime.decrypt::<Unsigned256>(c, &private_key).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values, [1u64, 2, 10].map(Unsigned256::from).to_vec());
+
+        assert!(load_ciphertext_dir(dir.path(), true).is_err());
+    }
+
+    #[test]
+    fn compacts_old_ciphertexts_into_archive() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("cache");
+        std::fs::create_dir(&dir).unwrap();
+        let write = |n: u64| {
+            let ciphertext = runtime.encrypt(Unsigned256::from(n), &public_key).unwrap();
+            ciphertext.write(dir.join(format!("{}.bin", n))).unwrap();
+        };
+        let decrypt_all = |ciphertexts: Vec<Ciphertext>| {
+            ciphertexts
+                .iter()
+                .map(|c| runtime.decrypt::<Unsigned256>(c, &private_key).unwrap())
+                .collect::<Vec<_>>()
+        };
+        for n in [1u64, 2, 3, 10] {
+            write(n);
+        }
+        std::fs::write(dir.join("notes.txt"), "not a ciphertext").unwrap();
+
+        let archive = compact_ciphertext_dir(&dir, 2).unwrap();
+        assert_eq!(archive, root.path().join("cache.archive"));
+        assert_eq!(
+            decrypt_all(load_ciphertext_archive(&archive).unwrap()),
+            [1u64, 2].map(Unsigned256::from).to_vec()
+        );
+        assert_eq!(
+            decrypt_all(load_ciphertext_dir(&dir, false).unwrap()),
+            [3u64, 10].map(Unsigned256::from).to_vec()
+        );
+        assert!(dir.join("notes.txt").exists());
+
+        // Compacting again appends to the archive.
+        write(11);
+        compact_ciphertext_dir(&dir, 1).unwrap();
+        assert_eq!(
+            decrypt_all(load_ciphertext_archive(&archive).unwrap()),
+            [1u64, 2, 3, 10].map(Unsigned256::from).to_vec()
+        );
+        assert_eq!(load_ciphertext_dir(&dir, false).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn bundle_roundtrips() {
+        let runtime = runtime();
+        let keys = runtime.generate_keys().unwrap();
+        let config = NetworkConfig::from(&PARASOL);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.json");
+
+        export_bundle(&path, "correct horse", &keys, &config).unwrap();
+
+        let (imported_keys, imported_config) = import_bundle(&path, "correct horse").unwrap();
+        assert!(imported_keys.0 == keys.0);
+        assert_eq!(
+            imported_keys.1.as_bytes().unwrap(),
+            keys.1.as_bytes().unwrap()
+        );
+        assert_eq!(imported_config, config);
+
+        assert!(matches!(
+            import_bundle(&path, "battery staple"),
+            Err(Error::Passphrase)
+        ));
+    }
+
+    #[test]
+    fn storage_keys_are_deterministic_per_context() {
+        use crate::testing::{ALICE, BOB};
+
+        let key = derive_storage_key(&ALICE, "ciphertext cache");
+        assert_eq!(key, derive_storage_key(&ALICE, "ciphertext cache"));
+        assert_ne!(key, derive_storage_key(&ALICE, "key cache"));
+        assert_ne!(key, derive_storage_key(&BOB, "ciphertext cache"));
+    }
+}
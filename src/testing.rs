@@ -5,16 +5,40 @@
 //! ```text
 //! cargo install --git https://github.com/Sunscreen-tech/foundry --profile local anvil
 //! ```
-use std::{str::FromStr, sync::Arc};
+use std::{
+    future::Future,
+    io::{BufRead, BufReader},
+    net::TcpListener,
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    str::FromStr,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use ethers::{
     prelude::Lazy,
-    providers::{Http, Provider},
+    providers::{Http, Middleware, Provider},
     signers::{LocalWallet, Signer},
+    types::{Chain, TransactionReceipt, TransactionRequest, U256},
     utils::{Anvil, AnvilInstance},
 };
 
-use super::SignedMiddleware;
+#[cfg(feature = "ipc")]
+use ethers::{prelude::SignerMiddleware, providers::Ipc};
+
+use super::{
+    batch::{BatchHttp, BatchOptions},
+    batched_http_provider, http_provider,
+    tx::{self, middleware_error, SendOptions},
+    AsBytes, AsNum, Ciphertext, Error, FheRuntime, Params, PrivateKey, PublicKey, Result,
+    SignedMiddleware, Unsigned256, DEFAULT_REQUEST_TIMEOUT,
+};
+#[cfg(feature = "ipc")]
+use super::{ipc_provider, IpcSignedMiddleware};
 
 /// A mnemonic for anvil to guarantee determinism. You must use this value to use the wallets for
 /// [`ALICE`] and [`BOB`] below.
@@ -25,23 +49,132 @@ pub const ANVIL_MNEMONIC: &str =
 /// [`ANVIL_MNEMONIC`].
 ///
 /// Public address: 0xb5f27c716e44ffe48fd6622983c651355ad8c75a
-pub static ALICE: Lazy<LocalWallet> = Lazy::new(|| {
-    LocalWallet::from_str("0x1c0eb5244c165957525ef389fc14fac4424feaaefabf87c7e4e15bcc7b425e15")
-        .unwrap()
-});
+pub static ALICE: Lazy<LocalWallet> =
+    Lazy::new(|| LocalWallet::from_str(alice_private_key()).unwrap());
 
 /// A wallet for a test user, creatively named Bob. This user exists whenever Anvil is invoked with
 /// [`ANVIL_MNEMONIC`].
 ///
 /// Public address: 0x00d88e763c5764e69dd667fa8073d48022a4afef
-pub static BOB: Lazy<LocalWallet> = Lazy::new(|| {
-    LocalWallet::from_str("0x3b42a2df3c658b156b8240e1891723fab65ae0b97f9f5bba2abd5e240065baa1")
-        .unwrap()
-});
+pub static BOB: Lazy<LocalWallet> = Lazy::new(|| LocalWallet::from_str(bob_private_key()).unwrap());
+
+/// The hex encoded private key of [`ALICE`], e.g. to use the same account in test fixtures written
+/// in other languages, such as a Hardhat test talking to the same anvil node. It is derived from
+/// [`ANVIL_MNEMONIC`] at index 0 and is public, so never use it outside of tests.
+pub fn alice_private_key() -> &'static str {
+    "0x1c0eb5244c165957525ef389fc14fac4424feaaefabf87c7e4e15bcc7b425e15"
+}
+
+/// The hex encoded private key of [`BOB`], derived from [`ANVIL_MNEMONIC`] at index 1. See
+/// [`alice_private_key`].
+pub fn bob_private_key() -> &'static str {
+    "0x3b42a2df3c658b156b8240e1891723fab65ae0b97f9f5bba2abd5e240065baa1"
+}
+
+/// How long [`Node::spawn_from()`] waits for anvil to start listening.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often [`Node::wait_until_ready()`] retries `eth_chainId`.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 /// A simple way to construct and run a local node for development purposes.
+///
+/// A node either spawns its own anvil process, or [connects](Self::connect()) to one that is
+/// already running. A spawned anvil process is killed and reaped when the node is dropped,
+/// including when a test panics. Use [`Self::shutdown()`] to learn how the process exited.
 pub struct Node {
-    pub anvil: AnvilInstance,
+    /// The anvil process, if this node spawned it.
+    pub anvil: Option<AnvilProcess>,
+    /// The HTTP endpoint of the node.
+    pub endpoint: String,
+    /// The chain ID of the node.
+    pub chain_id: u64,
+    /// The IPC socket of the node, if it serves one; see [`NodeOptions::ipc_path`].
+    pub ipc_path: Option<PathBuf>,
+    /// How often providers from [`Self::provider()`] poll for new blocks, e.g. while awaiting
+    /// pending transactions. If `None`, the ethers default for local endpoints is used.
+    pub poll_interval: Option<Duration>,
+    /// How long providers from [`Self::provider()`] wait for a response to each RPC request before
+    /// failing. Defaults to [`DEFAULT_REQUEST_TIMEOUT`].
+    pub request_timeout: Duration,
+}
+
+/// Options for spawning a [`Node`] with [`Node::spawn_with()`].
+#[derive(Debug, Clone, Default)]
+pub struct NodeOptions {
+    /// The base fee of the genesis block, in wei. If `None`, anvil's default is used.
+    pub base_fee: Option<u64>,
+    /// Disable mining entirely, so that blocks, including those containing transactions, are
+    /// only produced by [`Node::mine()`].
+    pub no_mining: bool,
+    /// Also serve the node over IPC at this path, for use with `Node::ipc_provider()` when the
+    /// `ipc` feature is enabled.
+    pub ipc_path: Option<PathBuf>,
+}
+
+/// An anvil process spawned by a [`Node`]. The process is killed when this is dropped, and
+/// unless it was spawned from an ethers [`Anvil`], also waited for so that it does not linger as a
+/// zombie.
+pub struct AnvilProcess {
+    process: Option<Process>,
+    port: u16,
+}
+
+/// How an [`AnvilProcess`] was spawned.
+enum Process {
+    /// Spawned by [`Node::spawn_from()`], which owns the child process.
+    Child(Child),
+    /// Spawned from an ethers [`Anvil`], which kills the process when dropped but never reaps it.
+    Instance(AnvilInstance),
+}
+
+impl AnvilProcess {
+    /// The OS process ID of anvil, or `None` if it was spawned from an ethers [`Anvil`], which does
+    /// not expose it.
+    pub fn id(&self) -> Option<u32> {
+        match &self.process {
+            Some(Process::Child(child)) => Some(child.id()),
+            _ => None,
+        }
+    }
+
+    /// The port anvil listens on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The HTTP endpoint of anvil.
+    pub fn endpoint(&self) -> String {
+        format!("http://localhost:{}", self.port)
+    }
+
+    /// The WebSocket endpoint of anvil.
+    pub fn ws_endpoint(&self) -> String {
+        format!("ws://localhost:{}", self.port)
+    }
+
+    /// Kill anvil and wait for it to exit, returning how it exited if this is known.
+    fn kill(&mut self) -> std::io::Result<Option<ExitStatus>> {
+        match self.process.take() {
+            Some(Process::Child(mut child)) => {
+                // Killing fails if anvil already exited, in which case waiting still reaps it.
+                let _ = child.kill();
+                child.wait().map(Some)
+            }
+            Some(Process::Instance(instance)) => {
+                // The instance kills anvil when dropped, but does not report how it exited.
+                drop(instance);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Drop for AnvilProcess {
+    fn drop(&mut self) {
+        let _ = self.kill();
+    }
 }
 
 impl Default for Node {
@@ -50,49 +183,497 @@ impl Default for Node {
     }
 }
 
+/// Spawn a node from an ethers [`Anvil`] builder, as [`Node::spawn_from()`] did before it took a
+/// [`Command`]. Prefer [`Node::spawn_from()`]: ethers kills anvil when the node is dropped, but
+/// does not wait for it, so it lingers as a zombie until the test process exits.
+impl From<Anvil> for Node {
+    fn from(anvil: Anvil) -> Self {
+        let instance = anvil.spawn();
+        Self {
+            endpoint: instance.endpoint(),
+            chain_id: instance.chain_id(),
+            anvil: Some(AnvilProcess {
+                port: instance.port(),
+                process: Some(Process::Instance(instance)),
+            }),
+            ipc_path: None,
+            poll_interval: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+}
+
 impl Node {
     /// Spawn the node (i.e. launch the anvil subprocess) with default configuration.
     ///
     /// Note this configuration allows you to specify where the anvil executable lives via an
     /// environment variable `ANVIL_PATH`.
     pub fn spawn() -> Self {
-        let anvil = std::env::var("ANVIL_PATH")
-            .map(Anvil::at)
-            .unwrap_or_else(|_| Anvil::new())
-            .mnemonic(ANVIL_MNEMONIC)
+        Self::spawn_with(NodeOptions::default())
+    }
+
+    /// Spawn the node like [`Self::spawn()`], and wait until it answers `eth_chainId` before
+    /// returning, so the first request does not race anvil's startup. Returns [`Error::Timeout`] if
+    /// it does not answer within `timeout`, in which case anvil is killed.
+    pub async fn spawn_ready(timeout: Duration) -> Result<Self> {
+        let node = Self::spawn();
+        node.wait_until_ready(timeout).await?;
+        Ok(node)
+    }
+
+    /// Wait until the node answers `eth_chainId`, e.g. after [connecting](Self::connect()) to a
+    /// node which may still be starting. Returns [`Error::Timeout`] if it does not answer within
+    /// `timeout`.
+    pub async fn wait_until_ready(&self, timeout: Duration) -> Result<()> {
+        let provider = self.provider();
+        let ready = async {
+            while provider.get_chainid().await.is_err() {
+                tokio::time::sleep(READY_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(timeout, ready)
+            .await
+            .map_err(|_| Error::Timeout(timeout))
+    }
+
+    /// Spawn the node like [`Self::spawn()`], with some additional configuration.
+    pub fn spawn_with(options: NodeOptions) -> Self {
+        let mut anvil =
+            Command::new(std::env::var("ANVIL_PATH").unwrap_or_else(|_| "anvil".into()));
+        anvil
+            .args(["--mnemonic", ANVIL_MNEMONIC])
             .args(["--gas-limit", "3000000000000000000"]);
-        Self::spawn_from(anvil)
+        if let Some(base_fee) = options.base_fee {
+            anvil.arg("--base-fee").arg(base_fee.to_string());
+        }
+        if options.no_mining {
+            anvil.arg("--no-mining");
+        }
+        if let Some(path) = &options.ipc_path {
+            anvil.arg("--ipc").arg(path);
+        }
+        Self {
+            ipc_path: options.ipc_path,
+            ..Self::spawn_from(anvil)
+        }
+    }
+
+    /// Spawn a node by running the provided anvil command. Use this if you want to customize the
+    /// way anvil is launched; otherwise just use [`Self::spawn()`]. An unused port is passed with
+    /// `--port`, so the command must not set one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if anvil cannot be run, or does not start listening within a few seconds.
+    pub fn spawn_from(mut command: Command) -> Self {
+        let port = unused_port();
+        let mut child = command
+            .arg("--port")
+            .arg(port.to_string())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("couldn't start anvil. Is anvil installed?");
+        let stdout = child.stdout.take().expect("anvil's stdout is piped");
+        // Kill anvil if it fails to start.
+        let mut anvil = AnvilProcess {
+            process: Some(Process::Child(child)),
+            port,
+        };
+
+        // Read anvil's output on another thread, so that waiting for it can time out. The thread
+        // keeps draining the output for as long as anvil runs, so anvil never blocks on a full pipe.
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => {
+                        let _ = sender.send(line);
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        let mut chain_id = None;
+        loop {
+            let line = match lines.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => panic!("timed out waiting for anvil to start"),
+                Err(RecvTimeoutError::Disconnected) => panic!(
+                    "anvil exited before it started listening: {:?}",
+                    anvil.kill()
+                ),
+            };
+            if line.contains("Listening on") {
+                let chain_id = chain_id.unwrap_or_else(|| Chain::AnvilHardhat.into());
+                return Self {
+                    endpoint: anvil.endpoint(),
+                    chain_id,
+                    anvil: Some(anvil),
+                    ipc_path: None,
+                    poll_interval: None,
+                    request_timeout: DEFAULT_REQUEST_TIMEOUT,
+                };
+            }
+            if let Some((_, rest)) = line.split_once("Chain ID:") {
+                chain_id = rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(|id| id.parse().ok());
+            }
+        }
     }
 
-    /// Spawn a node from the provided [`Anvil`]. Use this if you want to customize the way
-    /// anvil is launched; otherwise just use [`Self::spawn()`].
-    pub fn spawn_from(anvil: Anvil) -> Self {
+    /// Attach to a node which is already running at `endpoint`, e.g. one anvil instance shared by
+    /// several test processes. The node is left running when this is dropped.
+    pub fn connect(endpoint: &str, chain_id: u64) -> Self {
         Self {
-            anvil: anvil.spawn(),
+            anvil: None,
+            endpoint: endpoint.to_owned(),
+            chain_id,
+            ipc_path: None,
+            poll_interval: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
         }
     }
 
-    /// Get an http-based [`Provider`] from this anvil instance.
+    /// Get an http-based [`Provider`] for this node.
     pub fn provider(&self) -> Provider<Http> {
-        Provider::<Http>::try_from(self.anvil.endpoint()).unwrap()
+        let provider = http_provider(&self.endpoint, self.request_timeout);
+        match self.poll_interval {
+            Some(interval) => provider.interval(interval),
+            None => provider,
+        }
+    }
+
+    /// Get a [`Provider`] for this node which combines concurrent requests into JSON-RPC batches.
+    pub fn batched_provider(&self, options: BatchOptions) -> Provider<BatchHttp> {
+        let provider = batched_http_provider(&self.endpoint, self.request_timeout, options);
+        match self.poll_interval {
+            Some(interval) => provider.interval(interval),
+            None => provider,
+        }
     }
 
     /// Construct a client with signable middleware for this node. This is useful when
     /// instantiating an [`ethers::contract::Contract`], which underlies the Solidity to Rust
     /// contract bindings.
     pub fn client(&self, wallet: LocalWallet) -> SignedMiddleware {
-        SignedMiddleware::new(
-            Arc::new(self.provider()),
-            wallet.with_chain_id(self.anvil.chain_id()),
-        )
+        self.client_with_chain_id(wallet, self.chain_id)
+    }
+
+    /// Construct a client like [`Self::client`] which signs for `chain_id` rather than the node's
+    /// own chain id, e.g. when anvil forks a chain whose id differs from the one it reports.
+    pub fn client_with_chain_id(&self, wallet: LocalWallet, chain_id: u64) -> SignedMiddleware {
+        SignedMiddleware::new(Arc::new(self.provider()), wallet.with_chain_id(chain_id))
+    }
+
+    /// Connect to this node over IPC. Returns [`Error::Unsupported`] if it does not serve IPC; see
+    /// [`NodeOptions::ipc_path`].
+    #[cfg(feature = "ipc")]
+    pub async fn ipc_provider(&self) -> Result<Provider<Ipc>> {
+        let path = self
+            .ipc_path
+            .as_ref()
+            .ok_or_else(|| Error::Unsupported("the node does not serve IPC".to_owned()))?;
+        let provider = ipc_provider(path).await?;
+        Ok(match self.poll_interval {
+            Some(interval) => provider.interval(interval),
+            None => provider,
+        })
+    }
+
+    /// Construct a client like [`Self::client`] which talks to this node over IPC.
+    #[cfg(feature = "ipc")]
+    pub async fn ipc_client(&self, wallet: LocalWallet) -> Result<IpcSignedMiddleware> {
+        Ok(SignerMiddleware::new(
+            Arc::new(self.ipc_provider().await?),
+            wallet.with_chain_id(self.chain_id),
+        ))
+    }
+
+    /// Kill the anvil process and wait for it to exit, releasing its port so that subsequent nodes
+    /// may reuse it. Returns how anvil exited, or `None` for [connected](Self::connect()) nodes,
+    /// which are left running, and for nodes spawned from an ethers [`Anvil`], whose exit is not
+    /// observed.
+    pub fn shutdown(mut self) -> Result<Option<ExitStatus>> {
+        match self.anvil.take() {
+            Some(mut anvil) => Ok(anvil.kill()?),
+            None => Ok(None),
+        }
+    }
+
+    /// Mine a single block (via `evm_mine`) containing any pending transactions, e.g. when the
+    /// node was spawned with [`NodeOptions::no_mining`].
+    pub async fn mine(&self) -> Result<()> {
+        self.provider()
+            .request::<_, U256>("evm_mine", None::<()>)
+            .await
+            .map_err(middleware_error)?;
+        Ok(())
+    }
+
+    /// Mine empty blocks (via `evm_mine`) until the chain reaches the given block number. This is
+    /// a no-op if the chain is already at or past `block`.
+    pub async fn mine_until(&self, block: u64) -> Result<()> {
+        let current = self
+            .provider()
+            .get_block_number()
+            .await
+            .map_err(middleware_error)?
+            .as_u64();
+        for _ in current..block {
+            self.mine().await?;
+        }
+        Ok(())
+    }
+}
+
+/// A port which nothing is listening on, for a spawned anvil.
+fn unused_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("failed to find an unused port")
+        .port()
+}
+
+/// Encrypt the boundary values of [`U256`] (zero, one, the midpoint, and the maximum along with
+/// their neighbours), paired with their plaintexts. Iterate over these to check that a contract
+/// handles extreme encrypted inputs.
+pub fn edge_case_ciphertexts(
+    runtime: &FheRuntime,
+    public_key: &PublicKey,
+) -> Result<Vec<(U256, Ciphertext)>> {
+    let mid = U256::one() << 255;
+    [
+        U256::zero(),
+        U256::one(),
+        mid - 1,
+        mid,
+        U256::MAX - 1,
+        U256::MAX,
+    ]
+    .iter()
+    .map(|value| Ok((*value, runtime.encrypt(value.to(), public_key)?)))
+    .collect()
+}
+
+/// Report the serialized size in bytes of an encrypted [`Unsigned256`] under each of the given
+/// parameter sets, e.g. to weigh calldata costs when choosing parameters. Note this generates a
+/// fresh keypair for each parameter set, which can be slow.
+pub fn size_report(params_sets: &[Params]) -> Result<Vec<(Params, usize)>> {
+    params_sets
+        .iter()
+        .map(|params| {
+            let runtime = FheRuntime::new(params)?;
+            let (public_key, _) = runtime.generate_keys()?;
+            let ciphertext = runtime.encrypt(Unsigned256::from(1), &public_key)?;
+            Ok((params.clone(), ciphertext.as_bytes()?.len()))
+        })
+        .collect()
+}
+
+/// Assert that a ciphertext, e.g. the encrypted result of a contract's homomorphic computation,
+/// decrypts to the result of the same computation in plaintext.
+///
+/// # Panics
+///
+/// Panics if the ciphertext cannot be decrypted, or if it decrypts to a different value. The panic
+/// message shows both values in decimal and hex along with their difference.
+#[track_caller]
+pub fn assert_homomorphic(
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+    encrypted_result: &Ciphertext,
+    expected_plaintext: U256,
+) {
+    let actual = decrypt_or_panic(
+        runtime,
+        private_key,
+        encrypted_result,
+        "the encrypted result",
+    );
+    if actual != expected_plaintext {
+        panic!(
+            "encrypted result does not match the plaintext computation\n  \
+             expected: {} ({:#x})\n    \
+             actual: {} ({:#x})\ndifference: {}",
+            expected_plaintext,
+            expected_plaintext,
+            actual,
+            actual,
+            difference(expected_plaintext, actual)
+        );
+    }
+}
+
+/// Assert that two ciphertexts decrypt to the same value, e.g. the encrypted results of two code
+/// paths which should be equivalent. Comparing the ciphertexts themselves is meaningless, as
+/// encryption is randomized.
+///
+/// # Panics
+///
+/// Panics if either ciphertext cannot be decrypted, or if they decrypt to different values. The
+/// panic message shows both values in decimal and hex along with their difference.
+#[track_caller]
+pub fn assert_ciphertexts_equal(
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+    a: &Ciphertext,
+    b: &Ciphertext,
+) {
+    let a = decrypt_or_panic(runtime, private_key, a, "the first ciphertext");
+    let b = decrypt_or_panic(runtime, private_key, b, "the second ciphertext");
+    if a != b {
+        panic!(
+            "ciphertexts decrypt to different values\n      \
+             left: {} ({:#x})\n     \
+             right: {} ({:#x})\ndifference: {}",
+            a,
+            a,
+            b,
+            b,
+            difference(a, b)
+        );
+    }
+}
+
+#[track_caller]
+fn decrypt_or_panic(
+    runtime: &FheRuntime,
+    private_key: &PrivateKey,
+    ciphertext: &Ciphertext,
+    what: &str,
+) -> U256 {
+    match runtime.decrypt::<Unsigned256>(ciphertext, private_key) {
+        Ok(value) => value.to(),
+        Err(e) => panic!("failed to decrypt {}: {}", what, e),
+    }
+}
+
+/// The signed difference `actual - expected`, for assertion messages.
+fn difference(expected: U256, actual: U256) -> String {
+    if actual > expected {
+        format!("+{}", actual - expected)
+    } else {
+        format!("-{}", expected - actual)
+    }
+}
+
+/// How long [`poll_until`] waits between reads.
+const POLL_UNTIL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Repeatedly invoke `call_fn`, e.g. a view function, until `predicate` holds for its result, and
+/// return that result. The predicate may fail, e.g. if it decrypts the result, which is useful to
+/// wait until an encrypted balance reaches an expected amount after asynchronous processing.
+///
+/// Returns [`Error::Timeout`] if the predicate does not hold within `timeout`. Errors from
+/// `call_fn` and `predicate` are returned immediately.
+pub async fn poll_until<T, F, Fut, P>(
+    mut call_fn: F,
+    mut predicate: P,
+    timeout: Duration,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+    P: FnMut(&T) -> Result<bool>,
+{
+    let poll = async {
+        loop {
+            let value = call_fn().await?;
+            if predicate(&value)? {
+                return Ok(value);
+            }
+            tokio::time::sleep(POLL_UNTIL_INTERVAL).await;
+        }
+    };
+    tokio::time::timeout(timeout, poll)
+        .await
+        .unwrap_or(Err(Error::Timeout(timeout)))
+}
+
+/// The gas used by [`compare_gas`]'s two ways of performing the same work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasComparison {
+    /// The total gas used by the separate calls.
+    pub separate: U256,
+    /// The gas used by the batched call.
+    pub batched: U256,
+}
+
+impl GasComparison {
+    /// How much gas batching saves, or zero if it costs more.
+    pub fn saved(&self) -> U256 {
+        self.separate.saturating_sub(self.batched)
     }
 }
 
+/// Measure the gas used by a sequence of separate calls and by a single batched call performing
+/// the same work, e.g. to quantify the savings of batching encrypted operations.
+///
+/// Both are run from the same starting state, and the node is reverted to that state afterwards,
+/// so this requires a local node supporting `evm_snapshot` and `evm_revert`. Returns
+/// [`Error::Batch`] if one of the separate calls fails, and [`Error::Reverted`] if the batched
+/// call does.
+pub async fn compare_gas<M: Middleware + 'static>(
+    client: &M,
+    single_calls: Vec<TransactionRequest>,
+    batched_call: TransactionRequest,
+) -> Result<GasComparison> {
+    let snapshot = take_snapshot(client).await?;
+    let separate = tx::send_batch(client, single_calls).await;
+    revert_to(client, snapshot).await?;
+
+    let snapshot = take_snapshot(client).await?;
+    let batched = tx::send(client, batched_call, &SendOptions::default())
+        .await
+        .and_then(tx::ensure_success);
+    revert_to(client, snapshot).await?;
+
+    Ok(GasComparison {
+        separate: separate?
+            .iter()
+            .map(gas_used)
+            .fold(U256::zero(), |a, b| a + b),
+        batched: gas_used(&batched?),
+    })
+}
+
+fn gas_used(receipt: &TransactionReceipt) -> U256 {
+    receipt.gas_used.unwrap_or_default()
+}
+
+async fn take_snapshot<M: Middleware + 'static>(client: &M) -> Result<U256> {
+    client
+        .provider()
+        .request("evm_snapshot", None::<()>)
+        .await
+        .map_err(middleware_error)
+}
+
+async fn revert_to<M: Middleware + 'static>(client: &M, snapshot: U256) -> Result<()> {
+    let reverted: bool = client
+        .provider()
+        .request("evm_revert", [snapshot])
+        .await
+        .map_err(middleware_error)?;
+    if !reverted {
+        return Err(anyhow::anyhow!("failed to revert to snapshot {}", snapshot).into());
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use ethers::{providers::Middleware, signers::Signer, types::TransactionRequest};
+    use std::net::TcpStream;
+
+    use ethers::signers::Signer;
 
     use super::*;
+    use crate::{fhe::decrypt, tests::runtime};
 
     #[tokio::test]
     async fn anvil_works() {
@@ -112,4 +693,315 @@ mod tests {
             .await
             .unwrap();
     }
+
+    #[test]
+    fn provider_uses_poll_interval() {
+        let node = Node {
+            poll_interval: Some(Duration::from_millis(10)),
+            ..Node::spawn()
+        };
+        assert_eq!(node.provider().get_interval(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn shutdown_releases_port() {
+        let node = Node::default();
+        let port = node.anvil.as_ref().unwrap().port();
+        assert!(TcpStream::connect(("localhost", port)).is_ok());
+
+        let status = node.shutdown().unwrap().unwrap();
+        assert!(!status.success());
+        assert!(TcpStream::connect(("localhost", port)).is_err());
+
+        let connected = Node::connect("http://localhost:8545", 31337);
+        assert!(connected.shutdown().unwrap().is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn drop_reaps_anvil() {
+        let node = Node::default();
+        let pid = node.anvil.as_ref().unwrap().id().unwrap();
+        drop(node);
+        // A zombie process keeps its entry until it is reaped.
+        assert!(!std::path::Path::new(&format!("/proc/{}", pid)).exists());
+    }
+
+    #[tokio::test]
+    async fn spawns_from_ethers_anvil() {
+        let anvil = std::env::var("ANVIL_PATH")
+            .map(Anvil::at)
+            .unwrap_or_else(|_| Anvil::new())
+            .mnemonic(ANVIL_MNEMONIC);
+        let node = Node::from(anvil);
+        assert!(node.anvil.as_ref().unwrap().id().is_none());
+
+        let client = node.client(ALICE.clone());
+        let tx = TransactionRequest::new().to(BOB.address()).value(1);
+        client.send_transaction(tx, None).await.unwrap();
+        assert!(node.shutdown().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn connect_attaches_without_owning() {
+        let node = Node::default();
+
+        let connected = Node::connect(&node.endpoint, node.chain_id);
+        let client = connected.client(ALICE.clone());
+        let tx = TransactionRequest::new().to(BOB.address()).value(1);
+        client.send_transaction(tx, None).await.unwrap();
+        drop(connected);
+
+        let block = node.provider().get_block_number().await.unwrap();
+        assert_eq!(block.as_u64(), 1);
+    }
+
+    #[tokio::test]
+    async fn spawn_with_fixed_base_fee_and_manual_mining() {
+        let node = Node::spawn_with(NodeOptions {
+            base_fee: Some(1_000_000_000),
+            no_mining: true,
+            ..NodeOptions::default()
+        });
+        let provider = node.provider();
+        let genesis = provider.get_block(0).await.unwrap().unwrap();
+        assert_eq!(genesis.base_fee_per_gas, Some(U256::from(1_000_000_000)));
+
+        let client = node.client(ALICE.clone());
+        let tx = TransactionRequest::new().to(BOB.address()).value(1);
+        let tx_hash = client.send_transaction(tx, None).await.unwrap().tx_hash();
+        assert!(client
+            .get_transaction_receipt(tx_hash)
+            .await
+            .unwrap()
+            .is_none());
+
+        node.mine().await.unwrap();
+        let receipt = client.get_transaction_receipt(tx_hash).await.unwrap();
+        assert_eq!(receipt.unwrap().block_number, Some(1.into()));
+    }
+
+    #[cfg(feature = "ipc")]
+    #[tokio::test]
+    async fn ipc_client_sends_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let node = Node::spawn_with(NodeOptions {
+            ipc_path: Some(dir.path().join("anvil.ipc")),
+            ..NodeOptions::default()
+        });
+
+        let client = node.ipc_client(ALICE.clone()).await.unwrap();
+        let tx = TransactionRequest::new().to(BOB.address()).value(1);
+        let receipt = client
+            .send_transaction(tx, None)
+            .await
+            .unwrap()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.from, ALICE.address());
+        assert_eq!(
+            node.provider().get_block_number().await.unwrap(),
+            receipt.block_number.unwrap()
+        );
+
+        assert!(matches!(
+            Node::default().ipc_provider().await,
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn spawn_ready_serves_immediately() {
+        let node = Node::spawn_ready(Duration::from_secs(10)).await.unwrap();
+        let chain_id = node.provider().get_chainid().await.unwrap();
+        assert_eq!(chain_id.as_u64(), node.chain_id);
+
+        // Nothing listens on a port which was just released.
+        let port = node.anvil.as_ref().unwrap().port();
+        node.shutdown().unwrap();
+        let gone = Node::connect(&format!("http://localhost:{}", port), 31337);
+        assert!(matches!(
+            gone.wait_until_ready(Duration::from_millis(200)).await,
+            Err(Error::Timeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn mine_until_reaches_target() {
+        let node = Node::default();
+        let provider = node.provider();
+
+        node.mine_until(5).await.unwrap();
+        assert_eq!(provider.get_block_number().await.unwrap().as_u64(), 5);
+
+        // already past the target, so nothing is mined
+        node.mine_until(2).await.unwrap();
+        assert_eq!(provider.get_block_number().await.unwrap().as_u64(), 5);
+    }
+
+    #[test]
+    fn edge_case_ciphertexts_decrypt_to_plaintexts() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+
+        let cases = edge_case_ciphertexts(&runtime, &public_key).unwrap();
+        assert!(cases.iter().any(|(value, _)| value.is_zero()));
+        assert!(cases.iter().any(|(value, _)| *value == U256::MAX));
+        for (value, ciphertext) in cases {
+            let bytes = ciphertext.as_bytes().unwrap();
+            assert_eq!(decrypt(&runtime, &private_key, &bytes).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn size_report_measures_ciphertexts() {
+        let params = runtime().params().clone();
+
+        let report = size_report(std::slice::from_ref(&params)).unwrap();
+        assert_eq!(report.len(), 1);
+        assert!(report[0].0 == params);
+        assert!(report[0].1 > 0);
+    }
+
+    #[test]
+    fn assert_homomorphic_compares_with_plaintext() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let sum = runtime
+            .encrypt(Unsigned256::from(2 + 3), &public_key)
+            .unwrap();
+
+        assert_homomorphic(&runtime, &private_key, &sum, U256::from(5));
+
+        let mismatch = std::panic::catch_unwind(|| {
+            assert_homomorphic(&runtime, &private_key, &sum, U256::from(7))
+        })
+        .unwrap_err();
+        let message = mismatch.downcast_ref::<String>().unwrap();
+        assert!(message.contains("\n  expected: 7 (0x7)\n"));
+        assert!(message.contains("\n    actual: 5 (0x5)\n"));
+        assert!(message.ends_with("\ndifference: -2"));
+    }
+
+    #[tokio::test]
+    async fn poll_until_waits_for_condition() {
+        use ethers::types::{Address, Bytes, H256};
+
+        let node = Node::default();
+        let provider = node.provider();
+
+        // Runtime code which returns storage slot 0.
+        let contract = Address::repeat_byte(0x51);
+        let code = Bytes::from(vec![
+            0x60, 0x00, 0x54, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+        ]);
+        provider
+            .request::<_, ()>("anvil_setCode", (contract, code))
+            .await
+            .unwrap();
+        let read = || async {
+            let tx = TransactionRequest::new().to(contract).into();
+            let output = provider.call(&tx, None).await.map_err(middleware_error)?;
+            Ok(U256::from_big_endian(&output))
+        };
+
+        let setter = node.provider();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            setter
+                .request::<_, bool>(
+                    "anvil_setStorageAt",
+                    (contract, H256::zero(), H256::from_low_u64_be(5)),
+                )
+                .await
+                .unwrap();
+        });
+
+        let value = poll_until(
+            read,
+            |value| Ok(*value == U256::from(5)),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(value, U256::from(5));
+
+        assert!(matches!(
+            poll_until(
+                read,
+                |value| Ok(value.is_zero()),
+                Duration::from_millis(300)
+            )
+            .await,
+            Err(Error::Timeout(_))
+        ));
+    }
+
+    #[test]
+    fn assert_ciphertexts_equal_compares_plaintexts() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let encrypt = |value: u64| {
+            runtime
+                .encrypt(Unsigned256::from(value), &public_key)
+                .unwrap()
+        };
+        let (a, b, c) = (encrypt(9), encrypt(9), encrypt(12));
+        assert_ne!(a.as_bytes().unwrap(), b.as_bytes().unwrap());
+
+        assert_ciphertexts_equal(&runtime, &private_key, &a, &b);
+
+        let mismatch =
+            std::panic::catch_unwind(|| assert_ciphertexts_equal(&runtime, &private_key, &a, &c))
+                .unwrap_err();
+        let message = mismatch.downcast_ref::<String>().unwrap();
+        assert!(message.contains("\n      left: 9 (0x9)\n"));
+        assert!(message.contains("\n     right: 12 (0xc)\n"));
+        assert!(message.ends_with("\ndifference: +3"));
+    }
+
+    #[test]
+    fn exports_test_account_keys() {
+        for (key, wallet) in [(alice_private_key(), &*ALICE), (bob_private_key(), &*BOB)] {
+            assert!(key.starts_with("0x"));
+            assert_eq!(
+                LocalWallet::from_str(key).unwrap().address(),
+                wallet.address()
+            );
+        }
+    }
+
+    #[test]
+    fn client_signs_for_overridden_chain_id() {
+        let node = Node::connect("http://127.0.0.1:8545", 31337);
+        assert_eq!(node.client(ALICE.clone()).signer().chain_id(), 31337);
+        assert_eq!(
+            node.client_with_chain_id(ALICE.clone(), 1)
+                .signer()
+                .chain_id(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn compares_gas_of_separate_and_batched_calls() {
+        let node = Node::default();
+        let client = node.client(ALICE.clone());
+        let balance = client.get_balance(BOB.address(), None).await.unwrap();
+
+        let transfer = |value: u64| TransactionRequest::new().to(BOB.address()).value(value);
+        let comparison = compare_gas(&client, vec![transfer(1); 3], transfer(3))
+            .await
+            .unwrap();
+        assert_eq!(comparison.separate, U256::from(3 * 21_000));
+        assert_eq!(comparison.batched, U256::from(21_000));
+        assert_eq!(comparison.saved(), U256::from(2 * 21_000));
+
+        // The node is left as it was.
+        assert_eq!(
+            client.get_balance(BOB.address(), None).await.unwrap(),
+            balance
+        );
+    }
 }
@@ -1,13 +1,24 @@
 //! This module offers functionality for interacting with testnets by Sunscreen.
 
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use ethers::{
-    providers::{Http, Provider},
+    middleware::gas_oracle::GasOracle,
+    providers::{Http, Middleware, Provider},
     signers::{LocalWallet, Signer},
+    types::{transaction::eip2718::TypedTransaction, Address, TransactionReceipt, TxHash, U256},
 };
+use reqwest::StatusCode;
 
-use super::SignedMiddleware;
+use super::{
+    batch::{BatchHttp, BatchOptions},
+    batched_http_provider, http_provider,
+    tx::{self, middleware_error, SendOptions},
+    Error, Result, SignedMiddleware,
+};
 
 /// This module offers functionality for interacting with Sunscreen's Parasol testnet.
 pub mod parasol {
@@ -20,6 +31,7 @@ pub mod parasol {
     const FAUCET_URL: &str = "https://faucet.sunscreen.tech/";
 
     use super::TestnetProvider;
+    use crate::DEFAULT_REQUEST_TIMEOUT;
 
     /// A provider for Sunscreen's Parasol testnet.
     ///
@@ -49,6 +61,11 @@ pub mod parasol {
         rpc_url: RPC_URL,
         chain_id: CHAIN_ID,
         faucet_url: FAUCET_URL,
+        faucet_api: None,
+        gas_oracle: None,
+        poll_interval: None,
+        request_timeout: DEFAULT_REQUEST_TIMEOUT,
+        confirmations: 1,
     };
 }
 
@@ -57,12 +74,80 @@ pub struct TestnetProvider {
     pub rpc_url: &'static str,
     pub chain_id: u64,
     pub faucet_url: &'static str,
+    /// The routes and JSON fields of the faucet's API, or `None` if they are not known, in which
+    /// case the faucet cannot be used from this crate.
+    pub faucet_api: Option<FaucetApi>,
+    /// Constructs a gas oracle to price outgoing transactions in [`Self::send`]. If `None`, the
+    /// node's suggested gas price is used.
+    pub gas_oracle: Option<fn() -> Box<dyn GasOracle>>,
+    /// How often providers poll for new blocks, e.g. while awaiting pending transactions. Higher
+    /// values reduce load on the public RPC. If `None`, the ethers default is used.
+    pub poll_interval: Option<Duration>,
+    /// How long providers wait for a response to each RPC request before failing. This is separate
+    /// from how long [`Self::send`] waits for a transaction to be mined.
+    pub request_timeout: Duration,
+    /// How many blocks [`Self::send_confirmed`] waits for, counting the block a transaction is
+    /// mined in.
+    pub confirmations: u64,
+}
+
+/// The routes and JSON fields of a faucet's HTTP API, used by [`TestnetProvider::faucet_info`]
+/// and [`TestnetProvider::request_funds_and_wait`].
+///
+/// Sunscreen has not published the API of its faucets, so no preset is provided; describe the API
+/// of the faucet in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaucetApi {
+    /// The route, relative to [`TestnetProvider::faucet_url`], which reports the drip amount and
+    /// cooldown with a `GET`, or `None` if the faucet has no such route.
+    pub info_path: Option<&'static str>,
+    /// The field of the info response holding the drip amount in wei, as a hex string.
+    pub amount_field: &'static str,
+    /// The field of the info response holding the cooldown in seconds, as a number.
+    pub cooldown_field: &'static str,
+    /// The route, relative to [`TestnetProvider::faucet_url`], which accepts fund requests with a
+    /// `POST`. An empty path is the faucet URL itself.
+    pub request_path: &'static str,
+    /// The field of the fund request's JSON body holding the address to fund, as a hex string.
+    pub address_field: &'static str,
+}
+
+/// How much a faucet gives out per request, and how often.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaucetInfo {
+    /// The amount of wei sent per request.
+    pub drip_amount: U256,
+    /// How long an address must wait between requests.
+    pub cooldown: Duration,
+}
+
+/// The progress of a faucet request made with [`TestnetProvider::request_funds_and_wait`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FundingStatus {
+    /// The faucet accepted the request.
+    Requested,
+    /// The balance was checked and has not increased yet, this long after the request.
+    Waiting(Duration),
 }
 
 impl TestnetProvider {
     /// Construct a [`Provider<Http>`] for the testnet.
     pub fn provider(&self) -> Provider<Http> {
-        Provider::try_from(self.rpc_url).unwrap()
+        let provider = http_provider(self.rpc_url, self.request_timeout);
+        match self.poll_interval {
+            Some(interval) => provider.interval(interval),
+            None => provider,
+        }
+    }
+
+    /// Construct a [`Provider`] for the testnet which combines concurrent requests into JSON-RPC
+    /// batches, e.g. to speed up many small reads.
+    pub fn batched_provider(&self, options: BatchOptions) -> Provider<BatchHttp> {
+        let provider = batched_http_provider(self.rpc_url, self.request_timeout, options);
+        match self.poll_interval {
+            Some(interval) => provider.interval(interval),
+            None => provider,
+        }
     }
 
     /// Construct a client with signable middleware for this testnet. This is useful when
@@ -73,4 +158,473 @@ impl TestnetProvider {
         let middleware = SignedMiddleware::new(provider, wallet.with_chain_id(self.chain_id));
         Arc::new(middleware)
     }
+
+    /// The [`SendOptions`] appropriate for this testnet.
+    pub fn send_options(&self) -> SendOptions {
+        SendOptions {
+            gas_oracle: self.gas_oracle.map(|oracle| oracle()),
+            ..SendOptions::default()
+        }
+    }
+
+    /// The faucet's API, or [`Error::Unsupported`] if [`Self::faucet_api`] is not configured.
+    fn configured_faucet_api(&self) -> Result<&FaucetApi> {
+        self.faucet_api.as_ref().ok_or_else(|| {
+            Error::Unsupported(format!(
+                "the API of the faucet at {} is not configured",
+                self.faucet_url
+            ))
+        })
+    }
+
+    /// Query the faucet's info route for its drip amount and cooldown, e.g. to plan how many
+    /// accounts can be funded within its rate limits. The route and the fields of its JSON
+    /// response are given by [`Self::faucet_api`].
+    ///
+    /// Returns [`Error::Unsupported`] if the faucet's API is not configured, or if the faucet does
+    /// not expose this route.
+    pub async fn faucet_info(&self) -> Result<FaucetInfo> {
+        let api = self.configured_faucet_api()?;
+        let unsupported = || {
+            Error::Unsupported(format!(
+                "faucet at {} does not report its drip amount and cooldown",
+                self.faucet_url
+            ))
+        };
+        let path = api.info_path.ok_or_else(unsupported)?;
+        let url = reqwest::Url::parse(self.faucet_url)
+            .and_then(|url| url.join(path))
+            .map_err(anyhow::Error::new)?;
+        let response = reqwest::Client::builder()
+            .timeout(self.request_timeout)
+            .build()?
+            .get(url)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND || status == StatusCode::METHOD_NOT_ALLOWED {
+            return Err(unsupported());
+        }
+        if !status.is_success() {
+            return Err(Error::Http {
+                status: Some(status),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+        let info: serde_json::Value = response.json().await?;
+        let field = |name: &str| {
+            info.get(name)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("faucet info has no {:?} field", name))
+        };
+        let drip_amount: U256 =
+            serde_json::from_value(field(api.amount_field)?).map_err(anyhow::Error::new)?;
+        let cooldown: u64 =
+            serde_json::from_value(field(api.cooldown_field)?).map_err(anyhow::Error::new)?;
+        Ok(FaucetInfo {
+            drip_amount,
+            cooldown: Duration::from_secs(cooldown),
+        })
+    }
+
+    /// Request funds for `address` from the faucet and wait for them to arrive, reporting progress
+    /// to `callback`, e.g. to drive a spinner. Returns the amount the balance increased by, or
+    /// [`Error::Timeout`] if it has not increased within `timeout`.
+    ///
+    /// The route and body of the request are given by [`Self::faucet_api`]. Returns
+    /// [`Error::Unsupported`] without contacting the faucet or waiting if the faucet's API is not
+    /// configured, and if the faucet does not accept requests there.
+    pub async fn request_funds_and_wait<F: FnMut(FundingStatus)>(
+        &self,
+        address: Address,
+        timeout: Duration,
+        mut callback: F,
+    ) -> Result<U256> {
+        let api = self.configured_faucet_api()?;
+        let provider = self.provider();
+        let initial = provider
+            .get_balance(address, None)
+            .await
+            .map_err(middleware_error)?;
+
+        let url = reqwest::Url::parse(self.faucet_url)
+            .and_then(|url| url.join(api.request_path))
+            .map_err(anyhow::Error::new)?;
+        let mut body = serde_json::Map::new();
+        body.insert(api.address_field.to_owned(), serde_json::json!(address));
+        let response = reqwest::Client::builder()
+            .timeout(self.request_timeout)
+            .build()?
+            .post(url)
+            .json(&body)
+            .send()
+            .await?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND || status == StatusCode::METHOD_NOT_ALLOWED {
+            return Err(Error::Unsupported(format!(
+                "faucet at {} does not accept fund requests",
+                self.faucet_url
+            )));
+        }
+        if !status.is_success() {
+            return Err(Error::Http {
+                status: Some(status),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+        callback(FundingStatus::Requested);
+
+        let requested = Instant::now();
+        let poll = async {
+            loop {
+                tokio::time::sleep(provider.get_interval()).await;
+                let balance = provider
+                    .get_balance(address, None)
+                    .await
+                    .map_err(middleware_error)?;
+                if balance > initial {
+                    return Ok(balance - initial);
+                }
+                callback(FundingStatus::Waiting(requested.elapsed()));
+            }
+        };
+        tokio::time::timeout(timeout, poll)
+            .await
+            .unwrap_or(Err(Error::Timeout(timeout)))
+    }
+
+    /// Send a transaction on this testnet and wait for it to be mined, returning its receipt. See
+    /// [`tx::send`].
+    pub async fn send(
+        &self,
+        client: &SignedMiddleware,
+        tx: impl Into<TypedTransaction>,
+    ) -> Result<TransactionReceipt> {
+        tx::send(client, tx, &self.send_options()).await
+    }
+
+    /// Send a transaction on this testnet and wait for [`Self::confirmations`] blocks, returning
+    /// its up to date receipt. See [`tx::await_confirmations`].
+    pub async fn send_confirmed(
+        &self,
+        client: &SignedMiddleware,
+        tx: impl Into<TypedTransaction>,
+    ) -> Result<TransactionReceipt> {
+        let receipt = self.send(client, tx).await?;
+        tx::await_confirmations(client, &receipt, self.confirmations).await
+    }
+}
+
+/// Check that a faucet transaction was mined and sent funds to `expected`, returning the amount
+/// sent, e.g. to confirm the faucet funded the requested account. Faucets are expected to fund
+/// accounts with plain ether transfers.
+///
+/// Returns [`Error::Reverted`] if the transaction failed, and an error if it is not mined yet or
+/// was sent to a different address.
+pub async fn verify_faucet_receipt<M: Middleware + 'static>(
+    provider: &M,
+    tx_hash: TxHash,
+    expected: Address,
+) -> Result<U256> {
+    let receipt = provider
+        .get_transaction_receipt(tx_hash)
+        .await
+        .map_err(middleware_error)?
+        .ok_or_else(|| anyhow::anyhow!("faucet transaction {:?} is not mined", tx_hash))?;
+    let receipt = tx::ensure_success(receipt)?;
+    if receipt.to != Some(expected) {
+        return Err(anyhow::anyhow!(
+            "faucet transaction {:?} was sent to {:?}, expected {:?}",
+            tx_hash,
+            receipt.to,
+            expected
+        )
+        .into());
+    }
+    let tx = provider
+        .get_transaction(tx_hash)
+        .await
+        .map_err(middleware_error)?
+        .ok_or_else(|| anyhow::anyhow!("faucet transaction {:?} not found", tx_hash))?;
+    Ok(tx.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use ethers::types::TransactionRequest;
+
+    use super::*;
+    use crate::{
+        testing::{Node, ALICE, BOB},
+        DEFAULT_REQUEST_TIMEOUT,
+    };
+
+    /// The faucet API served by [`faucet_recording`]'s test servers.
+    const TEST_FAUCET_API: FaucetApi = FaucetApi {
+        info_path: Some("info"),
+        amount_field: "amount",
+        cooldown_field: "cooldown_seconds",
+        request_path: "",
+        address_field: "address",
+    };
+
+    /// Serve a single HTTP request with a canned response, returning a testnet whose faucet is
+    /// the server.
+    fn faucet_responding(response: &'static str) -> TestnetProvider {
+        faucet_recording(response).0
+    }
+
+    /// Serve a single HTTP request like [`faucet_responding`], also returning a receiver of the
+    /// request's head and body.
+    fn faucet_recording(response: &'static str) -> (TestnetProvider, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            let mut buf = [0; 1024];
+            while !is_complete_request(&request) {
+                let len = stream.read(&mut buf).unwrap();
+                if len == 0 {
+                    break;
+                }
+                request.push_str(&String::from_utf8_lossy(&buf[..len]));
+            }
+            let _ = sender.send(request);
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        let testnet = TestnetProvider {
+            rpc_url: "http://127.0.0.1:1/",
+            chain_id: 1,
+            faucet_url: Box::leak(url.into_boxed_str()),
+            faucet_api: Some(TEST_FAUCET_API),
+            gas_oracle: None,
+            poll_interval: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            confirmations: 1,
+        };
+        (testnet, receiver)
+    }
+
+    /// Whether `request` holds an HTTP request's whole head and body, going by its content length.
+    fn is_complete_request(request: &str) -> bool {
+        let (head, body) = match request.split_once("\r\n\r\n") {
+            Some(parts) => parts,
+            None => return false,
+        };
+        let content_length = head
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.trim().parse().ok())
+            .unwrap_or(0);
+        body.len() >= content_length
+    }
+
+    /// A canned HTTP response with a JSON body.
+    fn json_response(body: &str) -> &'static str {
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        Box::leak(response.into_boxed_str())
+    }
+
+    #[tokio::test]
+    async fn reads_faucet_info() {
+        let body = r#"{"amount":"0xde0b6b3a7640000","cooldown_seconds":86400}"#;
+        let testnet = faucet_responding(json_response(body));
+
+        let info = testnet.faucet_info().await.unwrap();
+        assert_eq!(info.drip_amount, U256::exp10(18));
+        assert_eq!(info.cooldown, Duration::from_secs(86400));
+
+        let (testnet, requests) = faucet_recording(json_response(r#"{"drip":"0x64","wait":60}"#));
+        let testnet = TestnetProvider {
+            faucet_api: Some(FaucetApi {
+                info_path: Some("api/limits"),
+                amount_field: "drip",
+                cooldown_field: "wait",
+                ..TEST_FAUCET_API
+            }),
+            ..testnet
+        };
+        let info = testnet.faucet_info().await.unwrap();
+        assert_eq!(info.drip_amount, U256::from(100));
+        assert_eq!(info.cooldown, Duration::from_secs(60));
+        assert!(requests.recv().unwrap().starts_with("GET /api/limits "));
+
+        let testnet = faucet_responding(json_response(r#"{"amount":"0x64"}"#));
+        assert!(testnet.faucet_info().await.is_err());
+
+        let testnet = TestnetProvider {
+            faucet_api: Some(FaucetApi {
+                info_path: None,
+                ..TEST_FAUCET_API
+            }),
+            ..faucet_responding(json_response("{}"))
+        };
+        assert!(matches!(
+            testnet.faucet_info().await,
+            Err(Error::Unsupported(_))
+        ));
+
+        // Without a configured API, the faucet is not contacted.
+        let (testnet, requests) = faucet_recording(json_response(body));
+        let testnet = TestnetProvider {
+            faucet_api: None,
+            ..testnet
+        };
+        assert!(matches!(
+            testnet.faucet_info().await,
+            Err(Error::Unsupported(_))
+        ));
+        assert!(requests.try_recv().is_err());
+
+        let testnet = faucet_responding("HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\n\r\n");
+        assert!(matches!(
+            testnet.faucet_info().await,
+            Err(Error::Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn waits_for_requested_funds() {
+        let node = Node::default();
+        let testnet = TestnetProvider {
+            rpc_url: Box::leak(node.endpoint.clone().into_boxed_str()),
+            poll_interval: Some(Duration::from_millis(10)),
+            ..faucet_responding("HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+        };
+        let address = Address::repeat_byte(0x11);
+        let provider = node.provider();
+
+        let mut statuses = Vec::new();
+        let (credited, funded) = tokio::join!(
+            testnet.request_funds_and_wait(address, Duration::from_secs(5), |status| {
+                statuses.push(status)
+            }),
+            async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                provider
+                    .request::<_, ()>("anvil_setBalance", (address, U256::from(500)))
+                    .await
+            }
+        );
+        funded.unwrap();
+        assert_eq!(credited.unwrap(), U256::from(500));
+        assert_eq!(statuses[0], FundingStatus::Requested);
+        assert!(matches!(statuses[1..], [FundingStatus::Waiting(_), ..]));
+
+        let testnet = TestnetProvider {
+            rpc_url: testnet.rpc_url,
+            poll_interval: testnet.poll_interval,
+            ..faucet_responding("HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+        };
+        assert!(matches!(
+            testnet
+                .request_funds_and_wait(address, Duration::from_millis(100), |_| {})
+                .await,
+            Err(Error::Timeout(_))
+        ));
+
+        let (faucet, requests) = faucet_recording("HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n");
+        let testnet = TestnetProvider {
+            rpc_url: testnet.rpc_url,
+            poll_interval: testnet.poll_interval,
+            faucet_api: Some(FaucetApi {
+                request_path: "api/claim",
+                address_field: "recipient",
+                ..TEST_FAUCET_API
+            }),
+            ..faucet
+        };
+        let _ = testnet
+            .request_funds_and_wait(address, Duration::from_millis(100), |_| {})
+            .await;
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /api/claim "));
+        assert!(request.ends_with(&format!(r#"{{"recipient":"{:?}"}}"#, address)));
+
+        // Without a configured API, no request is made and nothing is waited for.
+        let (faucet, requests) = faucet_recording("HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n");
+        let testnet = TestnetProvider {
+            rpc_url: testnet.rpc_url,
+            faucet_api: None,
+            ..faucet
+        };
+        assert!(matches!(
+            testnet
+                .request_funds_and_wait(address, Duration::from_secs(60), |_| {})
+                .await,
+            Err(Error::Unsupported(_))
+        ));
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn verifies_faucet_receipts() {
+        let node = Node::default();
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+        let tx = TransactionRequest::new().to(BOB.address()).value(1234);
+        let receipt = tx::send(&client, tx, &SendOptions::default())
+            .await
+            .unwrap();
+        let tx_hash = receipt.transaction_hash;
+
+        assert_eq!(
+            verify_faucet_receipt(&provider, tx_hash, BOB.address())
+                .await
+                .unwrap(),
+            U256::from(1234)
+        );
+        assert!(verify_faucet_receipt(&provider, tx_hash, ALICE.address())
+            .await
+            .is_err());
+        assert!(
+            verify_faucet_receipt(&provider, TxHash::repeat_byte(1), BOB.address())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn send_confirmed_waits_for_confirmations() {
+        let node = Node {
+            poll_interval: Some(Duration::from_millis(10)),
+            ..Node::spawn()
+        };
+        let testnet = TestnetProvider {
+            rpc_url: Box::leak(node.endpoint.clone().into_boxed_str()),
+            chain_id: node.chain_id,
+            faucet_url: "http://127.0.0.1:1/",
+            faucet_api: None,
+            gas_oracle: None,
+            poll_interval: node.poll_interval,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            confirmations: 3,
+        };
+        let client = node.client(ALICE.clone());
+        let start = node.provider().get_block_number().await.unwrap().as_u64();
+
+        let tx = TransactionRequest::new().to(BOB.address()).value(1);
+        let (receipt, mined) = tokio::join!(testnet.send_confirmed(&client, tx), async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            node.mine_until(start + 3).await
+        });
+        mined.unwrap();
+        let receipt = receipt.unwrap();
+        assert_eq!(receipt.block_number.unwrap().as_u64(), start + 1);
+        assert!(node.provider().get_block_number().await.unwrap().as_u64() >= start + 3);
+    }
 }
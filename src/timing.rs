@@ -0,0 +1,72 @@
+//! This module offers instrumentation of the FHE helpers in [`crate::fhe`], for profiling where
+//! time is spent. It is only available with the `timed` feature.
+
+use std::{sync::RwLock, time::Duration};
+
+use ethers::prelude::Lazy;
+
+/// A step of an FHE helper whose duration is reported to the [callback](set_callback).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Encrypt,
+    Decrypt,
+    /// Converting a ciphertext into bytes, e.g. after encryption.
+    Serialize,
+    /// Converting bytes into a ciphertext, e.g. before decryption.
+    Deserialize,
+}
+
+type Callback = Box<dyn Fn(Operation, Duration) + Send + Sync>;
+
+static CALLBACK: Lazy<RwLock<Option<Callback>>> = Lazy::new(Default::default);
+
+/// Report the duration of every subsequent [`Operation`] to `callback`, from any thread. This
+/// replaces any previously set callback.
+pub fn set_callback(callback: impl Fn(Operation, Duration) + Send + Sync + 'static) {
+    *CALLBACK.write().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(callback));
+}
+
+/// Stop reporting durations.
+pub fn clear_callback() {
+    *CALLBACK.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Report the duration of an operation to the callback, if one is set.
+pub(crate) fn record(operation: Operation, duration: Duration) {
+    if let Some(callback) = &*CALLBACK.read().unwrap_or_else(|e| e.into_inner()) {
+        callback(operation, duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use ethers::types::U256;
+
+    use super::*;
+    use crate::{fhe, tests::runtime};
+
+    #[test]
+    fn reports_encryption_separately_from_serialization() {
+        let runtime = runtime();
+        let (public_key, private_key) = runtime.generate_keys().unwrap();
+        let operations = Arc::new(Mutex::new(Vec::new()));
+        let recorded = operations.clone();
+        set_callback(move |operation, _| recorded.lock().unwrap().push(operation));
+
+        let bytes = fhe::encrypt(&runtime, &public_key, U256::from(3)).unwrap();
+        fhe::decrypt(&runtime, &private_key, &bytes).unwrap();
+        clear_callback();
+
+        let operations = operations.lock().unwrap();
+        for operation in [
+            Operation::Encrypt,
+            Operation::Serialize,
+            Operation::Deserialize,
+            Operation::Decrypt,
+        ] {
+            assert!(operations.contains(&operation), "{:?}", operation);
+        }
+    }
+}
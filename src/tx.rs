@@ -0,0 +1,1496 @@
+//! This module offers helpers for submitting transactions and interpreting the node's responses.
+
+use std::time::{Duration, Instant};
+
+use ethers::{
+    abi::{self, Abi, ParamType, Token},
+    contract::EthEvent,
+    middleware::gas_oracle::GasOracle,
+    providers::{Middleware, MiddlewareError, PendingTransaction, StreamExt},
+    signers::{LocalWallet, Signer},
+    types::{
+        transaction::{eip2718::TypedTransaction, eip2930::AccessList},
+        Address, Bytes, Eip2930TransactionRequest, Transaction, TransactionReceipt,
+        TransactionRequest, TxHash, U256, U64,
+    },
+    utils::hex,
+};
+use futures_util::{stream, Stream};
+
+use super::{fhe::peek_fhe_metadata, Error, Result};
+
+/// Options controlling how [`send`] submits a transaction.
+#[derive(Debug, Default)]
+pub struct SendOptions {
+    /// Fetch fees from this oracle rather than relying on the node's suggested gas price.
+    pub gas_oracle: Option<Box<dyn GasOracle>>,
+    /// Attach this EIP-2930 access list to the transaction, e.g. to cut the gas of calls touching
+    /// many storage slots. Legacy transactions are converted to EIP-2930 transactions to carry it.
+    pub access_list: Option<AccessList>,
+    /// Attach an access list generated by the node, see [`create_access_list`]. Ignored if
+    /// [`Self::access_list`] is set.
+    pub generate_access_list: bool,
+}
+
+/// Send a transaction and wait for it to be mined, returning its receipt.
+///
+/// If a gas oracle is configured in `options`, its suggested fees are applied to the transaction
+/// before it is submitted: EIP-1559 transactions receive the oracle's max fee and priority fee, and
+/// all other transactions receive its gas price. See [`dry_run`] to preview what would be sent.
+pub async fn send<M: Middleware + 'static>(
+    client: &M,
+    tx: impl Into<TypedTransaction>,
+    options: &SendOptions,
+) -> Result<TransactionReceipt> {
+    let tx = apply_options(client, tx.into(), options).await?;
+    let pending = client
+        .send_transaction(tx, None)
+        .await
+        .map_err(middleware_error)?;
+    let tx_hash = pending.tx_hash();
+    let receipt = pending.await.map_err(middleware_error)?;
+    receipt.ok_or(Error::Dropped(tx_hash))
+}
+
+/// A transaction previewed with [`dry_run`].
+#[derive(Debug, Clone)]
+pub struct DryRun {
+    /// The transaction as [`send`] would have submitted it, filled in by the client.
+    pub tx: TypedTransaction,
+    /// The output of the transaction when simulated via `eth_call`.
+    pub output: Bytes,
+}
+
+/// Prepare a transaction exactly like [`send`] with the same `options`, then fill it in, log it,
+/// and simulate it via `eth_call` without ever submitting it, e.g. to preview a destructive FHE
+/// operation before committing to it.
+///
+/// Returns [`Error::Revert`] if the transaction would revert.
+pub async fn dry_run<M: Middleware + 'static>(
+    client: &M,
+    tx: impl Into<TypedTransaction>,
+    options: &SendOptions,
+) -> Result<DryRun> {
+    let mut tx = apply_options(client, tx.into(), options).await?;
+    client
+        .fill_transaction(&mut tx, None)
+        .await
+        .map_err(middleware_error)?;
+    tracing::info!(?tx, "dry run: simulating transaction without submitting it");
+    let output = client.call(&tx, None).await.map_err(middleware_error)?;
+    Ok(DryRun { tx, output })
+}
+
+/// Apply the fees of the gas oracle and the access list configured in `options` to a transaction.
+async fn apply_options<M: Middleware + 'static>(
+    client: &M,
+    mut tx: TypedTransaction,
+    options: &SendOptions,
+) -> Result<TypedTransaction> {
+    if let Some(oracle) = &options.gas_oracle {
+        if let TypedTransaction::Eip1559(inner) = &mut tx {
+            let (max_fee, priority_fee) = oracle
+                .estimate_eip1559_fees()
+                .await
+                .map_err(anyhow::Error::new)?;
+            inner.max_fee_per_gas = Some(max_fee);
+            inner.max_priority_fee_per_gas = Some(priority_fee);
+        } else {
+            tx.set_gas_price(oracle.fetch().await.map_err(anyhow::Error::new)?);
+        }
+    }
+
+    if let Some(access_list) = &options.access_list {
+        attach_access_list(&mut tx, access_list.clone());
+    } else if options.generate_access_list {
+        let access_list = create_access_list(client, &tx).await?;
+        attach_access_list(&mut tx, access_list);
+    }
+    Ok(tx)
+}
+
+/// Ask the node for the EIP-2930 access list of the storage a transaction would touch, via
+/// `eth_createAccessList`. If the transaction has no sender, the client's default sender is used.
+pub async fn create_access_list<M: Middleware + 'static>(
+    client: &M,
+    tx: &TypedTransaction,
+) -> Result<AccessList> {
+    let mut tx = tx.clone();
+    if let (None, Some(sender)) = (tx.from(), client.default_sender()) {
+        tx.set_from(sender);
+    }
+    let created = client
+        .create_access_list(&tx, None)
+        .await
+        .map_err(middleware_error)?;
+    Ok(created.access_list)
+}
+
+/// Set a transaction's access list, converting legacy transactions, which cannot carry one, to
+/// EIP-2930 transactions.
+fn attach_access_list(tx: &mut TypedTransaction, access_list: AccessList) {
+    if let TypedTransaction::Legacy(legacy) = tx {
+        *tx =
+            TypedTransaction::Eip2930(Eip2930TransactionRequest::new(legacy.clone(), access_list));
+    } else {
+        tx.set_access_list(access_list);
+    }
+}
+
+/// Send a transaction, wait for it to be mined, and decode the first event of type `E` it emitted,
+/// e.g. a `Deposited` event carrying an encrypted amount. Events emitted by any contract the
+/// transaction called are considered.
+///
+/// Returns [`Error::Reverted`] if the transaction reverts, and an error if it emitted no such
+/// event.
+pub async fn send_and_get_event<E: EthEvent, M: Middleware + 'static>(
+    client: &M,
+    tx: impl Into<TypedTransaction>,
+) -> Result<E> {
+    let receipt = ensure_success(send(client, tx, &SendOptions::default()).await?)?;
+    receipt
+        .logs
+        .into_iter()
+        .find_map(|log| E::decode_log(&log.into()).ok())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "transaction {:?} emitted no {} event",
+                receipt.transaction_hash,
+                E::name()
+            )
+            .into()
+        })
+}
+
+/// Send a transaction and wait for it to be mined, replacing it with a higher priced copy each time
+/// it is not mined within `timeout`, e.g. to get unstuck during congestion.
+///
+/// Each replacement reuses the original nonce, so at most one of the copies is mined, and raises
+/// the fees by `bump_percent`. Nodes typically reject replacements bumped by less than 10%. Whichever
+/// copy is mined, its receipt is returned. Returns [`Error::Dropped`] if a copy is dropped and none
+/// of the copies was mined, and [`Error::Timeout`] with the time spent waiting if none of
+/// `max_attempts` copies is mined in time.
+pub async fn send_with_replacement<M: Middleware + 'static>(
+    client: &M,
+    tx: impl Into<TypedTransaction>,
+    bump_percent: u64,
+    max_attempts: usize,
+    timeout: Duration,
+) -> Result<TransactionReceipt> {
+    let mut tx = tx.into();
+    client
+        .fill_transaction(&mut tx, None)
+        .await
+        .map_err(middleware_error)?;
+
+    let started = Instant::now();
+    let mut sent = Vec::with_capacity(max_attempts);
+    let mut dropped = None;
+    for attempt in 0..max_attempts {
+        if attempt > 0 {
+            bump_fees(&mut tx, bump_percent);
+        }
+        let pending = match client.send_transaction(tx.clone(), None).await {
+            Ok(pending) => pending,
+            // An earlier copy was mined while we were preparing this one.
+            Err(e) => match middleware_error(e) {
+                Error::Nonce(_) => break,
+                e => return Err(e),
+            },
+        };
+        let tx_hash = pending.tx_hash();
+        sent.push(tx_hash);
+        match tokio::time::timeout(timeout, pending).await {
+            Ok(Ok(Some(receipt))) => return Ok(receipt),
+            // This copy was dropped, most likely because an earlier copy with the same nonce was
+            // mined in the meantime, which is looked for below.
+            Ok(Ok(None)) => {
+                dropped = Some(tx_hash);
+                break;
+            }
+            Ok(Err(e)) => return Err(middleware_error(e)),
+            Err(_) => {}
+        }
+    }
+
+    for tx_hash in sent {
+        let receipt = client
+            .get_transaction_receipt(tx_hash)
+            .await
+            .map_err(middleware_error)?;
+        if let Some(receipt) = receipt {
+            return Ok(receipt);
+        }
+    }
+    match dropped {
+        Some(tx_hash) => Err(Error::Dropped(tx_hash)),
+        None => Err(Error::Timeout(started.elapsed())),
+    }
+}
+
+/// Raise a transaction's fees by `percent`, and always by at least one wei.
+fn bump_fees(tx: &mut TypedTransaction, percent: u64) {
+    let bump = |fee: U256| (fee * (100 + percent) / 100).max(fee + 1);
+    if let TypedTransaction::Eip1559(inner) = tx {
+        inner.max_fee_per_gas = inner.max_fee_per_gas.map(bump);
+        inner.max_priority_fee_per_gas = inner.max_priority_fee_per_gas.map(bump);
+    } else if let Some(gas_price) = tx.gas_price() {
+        tx.set_gas_price(bump(gas_price));
+    }
+}
+
+/// The progress of a transaction sent with [`send_with_progress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxStatus {
+    /// The node accepted the transaction into its mempool.
+    Submitted(TxHash),
+    /// The transaction was included in a block, but may still have reverted.
+    Mined(Box<TransactionReceipt>),
+    /// The mined transaction executed successfully.
+    Confirmed,
+}
+
+/// Send a transaction and wait for it to be mined like [`send`], reporting each step to
+/// `callback`, e.g. to drive a progress indicator.
+///
+/// If the transaction reverts, [`Error::Reverted`] is returned after [`TxStatus::Mined`] is
+/// reported, and [`TxStatus::Confirmed`] never is.
+pub async fn send_with_progress<M: Middleware + 'static, F: FnMut(TxStatus)>(
+    client: &M,
+    tx: impl Into<TypedTransaction>,
+    mut callback: F,
+) -> Result<TransactionReceipt> {
+    let tx: TypedTransaction = tx.into();
+    let pending = client
+        .send_transaction(tx, None)
+        .await
+        .map_err(middleware_error)?;
+    let tx_hash = pending.tx_hash();
+    callback(TxStatus::Submitted(tx_hash));
+
+    let receipt = pending
+        .await
+        .map_err(middleware_error)?
+        .ok_or(Error::Dropped(tx_hash))?;
+    callback(TxStatus::Mined(Box::new(receipt.clone())));
+
+    let receipt = ensure_success(receipt)?;
+    callback(TxStatus::Confirmed);
+    Ok(receipt)
+}
+
+/// Wait until a mined transaction has `confirmations` confirmations, i.e. until its block is
+/// followed by `confirmations - 1` more, and return its up to date receipt.
+///
+/// The receipt is re-fetched on every new block. If the transaction is no longer in the block it
+/// was originally mined in, [`Error::Reorged`] is returned, even if it was since re-included
+/// elsewhere, so that callers never mistake a reorganized transaction for a final one.
+pub async fn await_confirmations<M: Middleware + 'static>(
+    client: &M,
+    receipt: &TransactionReceipt,
+    confirmations: u64,
+) -> Result<TransactionReceipt> {
+    let tx_hash = receipt.transaction_hash;
+    let mined_in = receipt
+        .block_number
+        .ok_or_else(|| anyhow::anyhow!("transaction {:?} is not mined", tx_hash))?
+        .as_u64();
+    let target = mined_in + confirmations.saturating_sub(1);
+    loop {
+        let current = client
+            .get_transaction_receipt(tx_hash)
+            .await
+            .map_err(middleware_error)?;
+        let current = match current {
+            Some(current) if current.block_hash == receipt.block_hash => current,
+            _ => return Err(Error::Reorged(tx_hash)),
+        };
+        let block = client
+            .get_block_number()
+            .await
+            .map_err(middleware_error)?
+            .as_u64();
+        if block >= target {
+            return Ok(current);
+        }
+        tokio::time::sleep(client.provider().get_interval()).await;
+    }
+}
+
+/// Watch for transactions sent from or to `address` in new blocks, e.g. to process incoming
+/// payments as they arrive. This relies on block filters, so it works over both HTTP and
+/// WebSocket providers.
+///
+/// Only transactions directly involving `address` are yielded; e.g. an FHE token transfer whose
+/// recipient only appears in the calldata is not. An error is yielded for each block that could
+/// not be fetched, and watching continues.
+pub async fn watch_address<'a, M: Middleware + 'static>(
+    client: &'a M,
+    address: Address,
+) -> Result<impl Stream<Item = Result<Transaction>> + 'a> {
+    let blocks = client.watch_blocks().await.map_err(middleware_error)?;
+    let transactions = blocks
+        .then(move |hash| async move {
+            client
+                .get_block_with_txs(hash)
+                .await
+                .map_err(middleware_error)
+        })
+        .flat_map(move |block| {
+            let transactions = match block {
+                Ok(Some(block)) => block
+                    .transactions
+                    .into_iter()
+                    .filter(|tx| tx.from == address || tx.to == Some(address))
+                    .map(Ok)
+                    .collect(),
+                Ok(None) => Vec::new(),
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(transactions)
+        });
+    Ok(transactions)
+}
+
+/// The most blocks [`transactions_from`] scans in one call.
+pub const MAX_SCAN_BLOCKS: u64 = 10_000;
+
+/// List the transactions sent by `address` in blocks `from_block` to `to_block` inclusive, in
+/// order, e.g. to build an account history.
+///
+/// This fetches every block in the range, so ranges longer than [`MAX_SCAN_BLOCKS`] are rejected
+/// rather than silently issuing a huge number of requests; page through longer histories with
+/// several calls instead.
+pub async fn transactions_from<M: Middleware + 'static>(
+    provider: &M,
+    address: Address,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<Transaction>> {
+    let blocks = to_block.saturating_sub(from_block).saturating_add(1);
+    if from_block > to_block || blocks > MAX_SCAN_BLOCKS {
+        return Err(anyhow::anyhow!(
+            "cannot scan blocks {} to {}, ranges must be ascending and at most {} blocks long",
+            from_block,
+            to_block,
+            MAX_SCAN_BLOCKS
+        )
+        .into());
+    }
+
+    let mut transactions = Vec::new();
+    for number in from_block..=to_block {
+        let block = provider
+            .get_block_with_txs(number)
+            .await
+            .map_err(middleware_error)?;
+        if let Some(block) = block {
+            transactions.extend(
+                block
+                    .transactions
+                    .into_iter()
+                    .filter(|tx| tx.from == address),
+            );
+        }
+    }
+    Ok(transactions)
+}
+
+/// Send a sequence of transactions in order, waiting for each to be mined before sending the
+/// next, and return all of their receipts.
+///
+/// Sending stops at the first transaction that fails or reverts, returning [`Error::Batch`] with
+/// its index. Note that transactions earlier in the batch have already been mined by then and are
+/// not undone.
+pub async fn send_batch<M: Middleware + 'static>(
+    client: &M,
+    txs: Vec<TransactionRequest>,
+) -> Result<Vec<TransactionReceipt>> {
+    let options = SendOptions::default();
+    let mut receipts = Vec::with_capacity(txs.len());
+    for (index, tx) in txs.into_iter().enumerate() {
+        let receipt = send(client, tx, &options)
+            .await
+            .and_then(ensure_success)
+            .map_err(|e| Error::Batch {
+                index,
+                source: Box::new(e),
+            })?;
+        receipts.push(receipt);
+    }
+    Ok(receipts)
+}
+
+/// Return [`Error::Reverted`] if the receipt indicates the transaction failed.
+pub(crate) fn ensure_success(receipt: TransactionReceipt) -> Result<TransactionReceipt> {
+    if receipt.status == Some(U64::zero()) {
+        return Err(Error::Reverted(receipt.transaction_hash));
+    }
+    Ok(receipt)
+}
+
+/// The gas used by a plain ether transfer to an externally owned account.
+const TRANSFER_GAS: u64 = 21_000;
+
+/// Transfer the entire balance of the `old` wallet to the `new` wallet, e.g. during a scheduled
+/// key rotation, and wait for the transfer to be mined.
+///
+/// The transferred amount is the old wallet's balance minus the exact cost of the transfer, so the
+/// old wallet is left empty.
+pub async fn rotate_wallet<M: Middleware + 'static>(
+    client: &M,
+    old: &LocalWallet,
+    new: &LocalWallet,
+) -> Result<TransactionReceipt> {
+    let chain_id = client.get_chainid().await.map_err(middleware_error)?;
+    let old = old.clone().with_chain_id(chain_id.as_u64());
+    let balance = client
+        .get_balance(old.address(), None)
+        .await
+        .map_err(middleware_error)?;
+    let nonce = client
+        .get_transaction_count(old.address(), None)
+        .await
+        .map_err(middleware_error)?;
+    let gas_price = client.get_gas_price().await.map_err(middleware_error)?;
+
+    let fee = gas_price * TRANSFER_GAS;
+    if balance <= fee {
+        return Err(anyhow::anyhow!(
+            "balance of {} wei does not cover the transfer fee of {} wei",
+            balance,
+            fee
+        )
+        .into());
+    }
+
+    let tx: TypedTransaction = TransactionRequest::new()
+        .from(old.address())
+        .to(new.address())
+        .value(balance - fee)
+        .gas(TRANSFER_GAS)
+        .gas_price(gas_price)
+        .nonce(nonce)
+        .chain_id(chain_id.as_u64())
+        .into();
+    let signature = old.sign_transaction(&tx).await?;
+    let tx_hash = send_raw(client, tx.rlp_signed(&signature)).await?;
+
+    let receipt = PendingTransaction::new(tx_hash, client.provider())
+        .await
+        .map_err(middleware_error)?;
+    receipt.ok_or(Error::Dropped(tx_hash))
+}
+
+/// Cancel a pending transaction by replacing it with a zero-value transfer from the sender to
+/// itself with the same `nonce`, e.g. to abort a mistakenly submitted FHE call before it is mined.
+/// The sender is the client's default sender, so `client` must be signing middleware. Returns the
+/// hash of the cancelling transaction without waiting for it to be mined.
+///
+/// The replacement pays twice the node's current gas price. Nodes reject replacements which do not
+/// outbid the original by enough (typically 10%), so a transaction priced far above the current
+/// gas price may need to be replaced with [`send_with_replacement`] instead.
+pub async fn cancel_tx<M: Middleware + 'static>(client: &M, nonce: U256) -> Result<TxHash> {
+    let sender = client
+        .default_sender()
+        .ok_or_else(|| anyhow::anyhow!("the client has no sender to cancel transactions of"))?;
+    let gas_price = client.get_gas_price().await.map_err(middleware_error)?;
+    let tx = TransactionRequest::new()
+        .from(sender)
+        .to(sender)
+        .value(0)
+        .gas(TRANSFER_GAS)
+        .gas_price(gas_price * 2)
+        .nonce(nonce);
+    let pending = client
+        .send_transaction(tx, None)
+        .await
+        .map_err(middleware_error)?;
+    Ok(pending.tx_hash())
+}
+
+/// The state of an account, as reported by [`account_summary`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountSummary {
+    pub address: Address,
+    /// The balance in wei, as of the latest block.
+    pub balance: U256,
+    /// The number of transactions sent from the account which have been mined, i.e. the nonce of
+    /// its next transaction if none are pending.
+    pub nonce: U256,
+    pub chain_id: U256,
+}
+
+/// Summarize the state of a client's account, e.g. for a CLI's `status` command. The account is
+/// the client's default sender, so `client` must be signing middleware.
+pub async fn account_summary<M: Middleware + 'static>(client: &M) -> Result<AccountSummary> {
+    let address = client
+        .default_sender()
+        .ok_or_else(|| anyhow::anyhow!("the client has no account to summarize"))?;
+    Ok(AccountSummary {
+        address,
+        balance: client
+            .get_balance(address, None)
+            .await
+            .map_err(middleware_error)?,
+        nonce: client
+            .get_transaction_count(address, None)
+            .await
+            .map_err(middleware_error)?,
+        chain_id: client.get_chainid().await.map_err(middleware_error)?,
+    })
+}
+
+/// Simulate a transaction via `eth_call` without submitting it, returning the call's output. This
+/// is a cheap way to preflight expensive FHE operations.
+///
+/// Returns [`Error::Revert`] if the transaction would revert.
+pub async fn simulate<M: Middleware + 'static>(
+    client: &M,
+    tx: impl Into<TypedTransaction>,
+) -> Result<Bytes> {
+    client
+        .call(&tx.into(), None)
+        .await
+        .map_err(middleware_error)
+}
+
+/// The estimated cost of a sequence of calls, returned by [`estimate_workflow_cost`].
+#[derive(Debug)]
+pub struct WorkflowCost {
+    /// The estimated cost in wei of each call, in order, or why it could not be estimated, e.g.
+    /// [`Error::Revert`] if the call would revert.
+    pub steps: Vec<Result<U256>>,
+    /// The sum of the costs of the calls which could be estimated.
+    pub total: U256,
+}
+
+impl WorkflowCost {
+    /// The indices of the calls which could not be estimated, e.g. because they would revert.
+    pub fn failed_steps(&self) -> Vec<usize> {
+        self.steps
+            .iter()
+            .enumerate()
+            .filter(|(_, step)| step.is_err())
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// Estimate the total cost in wei of a sequence of calls, e.g. to tell a user how much to fund an
+/// account with before starting a multi-step FHE workflow. Each call's gas estimate is priced at
+/// its own gas price if it has one, and otherwise at the node's current gas price. Calls may be
+/// anything convertible into a transaction, e.g. [`TransactionRequest`]s carrying calldata from
+/// [`encode_call`](crate::contract::encode_call), or the `tx` of a contract call.
+///
+/// Every call is estimated against the current chain state, so calls which depend on the effects
+/// of earlier calls in the sequence may be estimated inaccurately. A call which cannot be
+/// estimated, e.g. because it would revert, is flagged in [`WorkflowCost::steps`] and left out of
+/// the total, and estimating continues with the next call. Only failing to fetch the gas price is
+/// an error.
+pub async fn estimate_workflow_cost<M, I>(client: &M, calls: I) -> Result<WorkflowCost>
+where
+    M: Middleware + 'static,
+    I: IntoIterator,
+    I::Item: Into<TypedTransaction>,
+{
+    let gas_price = client.get_gas_price().await.map_err(middleware_error)?;
+    let mut steps = Vec::new();
+    let mut total = U256::zero();
+    for call in calls {
+        let call = call.into();
+        let step = client
+            .estimate_gas(&call, None)
+            .await
+            .map(|gas| gas * call.gas_price().unwrap_or(gas_price))
+            .map_err(middleware_error);
+        if let Ok(cost) = &step {
+            total += *cost;
+        }
+        steps.push(step);
+    }
+    Ok(WorkflowCost { steps, total })
+}
+
+/// Broadcast a pre-signed, RLP-encoded transaction via `eth_sendRawTransaction`, returning its
+/// hash. This is useful for relaying transactions produced by an offline signer.
+///
+/// Returns [`Error::AlreadyKnown`] if the node has already seen this transaction and
+/// [`Error::Nonce`] if its nonce is invalid for the sender; any other node error is passed
+/// through.
+pub async fn send_raw<M: Middleware + 'static>(provider: &M, raw: Bytes) -> Result<TxHash> {
+    let pending = provider
+        .send_raw_transaction(raw)
+        .await
+        .map_err(middleware_error)?;
+    Ok(pending.tx_hash())
+}
+
+/// Convert a middleware error into an [`Error`], picking out the JSON-RPC error responses that
+/// callers commonly need to handle.
+pub(crate) fn middleware_error<E: MiddlewareError + 'static>(e: E) -> Error {
+    if let Some(response) = e.as_error_response() {
+        if let Some(data) = response.as_revert_data() {
+            return Error::Revert {
+                reason: decode_revert_reason(&data),
+                data,
+            };
+        }
+        let message = response.message.to_lowercase();
+        if message.contains("already known") || message.contains("already imported") {
+            return Error::AlreadyKnown;
+        }
+        if message.contains("nonce too low") || message.contains("nonce too high") {
+            return Error::Nonce(response.message.clone());
+        }
+    }
+    Error::Other(e.into())
+}
+
+/// The selector of Solidity's `Error(string)`, used by `revert("reason")` and `require`.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// The selector of Solidity's `Panic(uint256)`, used by failed assertions, overflows etc.
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// Decode a human readable reason from revert data, if it is a standard Solidity error.
+pub(crate) fn decode_revert_reason(data: &[u8]) -> Option<String> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (selector, args) = data.split_at(4);
+    let param = if selector == ERROR_SELECTOR {
+        ParamType::String
+    } else if selector == PANIC_SELECTOR {
+        ParamType::Uint(256)
+    } else {
+        return None;
+    };
+    match abi::decode(&[param], args).ok()?.pop()? {
+        Token::String(reason) => Some(reason),
+        Token::Uint(code) => Some(format!("panic code {:#x}", code)),
+        _ => None,
+    }
+}
+
+/// Recover why a mined transaction failed by replaying it with `eth_call` on the state of the block
+/// before it, e.g. to diagnose a past FHE transaction. Returns `None` if the transaction succeeded,
+/// if its revert data is not a standard Solidity error (see [`decode_custom_error`] for those), or
+/// if it no longer reverts when replayed.
+///
+/// The replay does not include transactions mined before this one in the same block, so a failure
+/// which depended on them may not be reproduced. Failures other than reverts, e.g. running out of
+/// gas, are returned as errors.
+pub async fn revert_reason_from_receipt<M: Middleware + 'static>(
+    provider: &M,
+    receipt: &TransactionReceipt,
+) -> Result<Option<String>> {
+    if receipt.status == Some(U64::one()) {
+        return Ok(None);
+    }
+    let tx_hash = receipt.transaction_hash;
+    let block = receipt
+        .block_number
+        .ok_or_else(|| anyhow::anyhow!("transaction {:?} is not mined", tx_hash))?;
+    let tx = provider
+        .get_transaction(tx_hash)
+        .await
+        .map_err(middleware_error)?
+        .ok_or_else(|| anyhow::anyhow!("transaction {:?} not found", tx_hash))?;
+
+    let mut call = TransactionRequest::new()
+        .from(tx.from)
+        .data(tx.input)
+        .value(tx.value)
+        .gas(tx.gas);
+    if let Some(to) = tx.to {
+        call = call.to(to);
+    }
+    let parent = block.saturating_sub(U64::one());
+    match provider.call(&call.into(), Some(parent.into())).await {
+        Ok(_) => Ok(None),
+        Err(e) => match middleware_error(e) {
+            Error::Revert { reason, .. } => Ok(reason),
+            e => Err(e),
+        },
+    }
+}
+
+/// A contract-defined Solidity error, decoded by [`decode_custom_error`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedError {
+    /// The name of the error, e.g. `InsufficientEncryptedBalance`.
+    pub name: String,
+    /// The names and values of the error's parameters, in declaration order.
+    pub params: Vec<(String, Token)>,
+}
+
+/// Decode revert data as one of the custom errors defined in a contract's ABI, e.g. to translate
+/// `revert InsufficientEncryptedBalance(needed)` into a user-facing message.
+///
+/// Returns `None` if the data does not match any error in `abi`. Standard `Error(string)` and
+/// `Panic(uint256)` reverts are already decoded into [`Error::Revert`]'s reason.
+pub fn decode_custom_error(data: &Bytes, abi: &Abi) -> Option<DecodedError> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (selector, args) = data.split_at(4);
+    abi.errors()
+        .filter(|error| error.signature()[..4] == *selector)
+        .find_map(|error| {
+            let tokens = error.decode(args).ok()?;
+            let names = error.inputs.iter().map(|param| param.name.clone());
+            Some(DecodedError {
+                name: error.name.clone(),
+                params: names.zip(tokens).collect(),
+            })
+        })
+}
+
+/// Describe the calldata of a transaction calling a function in `abi`, e.g. for a debugging CLI
+/// inspecting what a contract call actually carried. Each argument is listed with its name, type
+/// and value; `bytes` arguments holding a serialized ciphertext are annotated with their size and
+/// encrypted type rather than dumped in full.
+///
+/// Returns an error if the transaction's input does not match a function in `abi`.
+pub async fn describe_tx_calldata<M: Middleware + 'static>(
+    provider: &M,
+    tx_hash: TxHash,
+    abi: &Abi,
+) -> Result<String> {
+    let tx = provider
+        .get_transaction(tx_hash)
+        .await
+        .map_err(middleware_error)?
+        .ok_or_else(|| anyhow::anyhow!("transaction {:?} not found", tx_hash))?;
+    describe_calldata(&tx.input, abi)
+}
+
+fn describe_calldata(input: &[u8], abi: &Abi) -> Result<String> {
+    if input.len() < 4 {
+        return Err(anyhow::anyhow!("calldata of {} bytes has no selector", input.len()).into());
+    }
+    let (selector, args) = input.split_at(4);
+    let function = abi
+        .functions()
+        .find(|function| function.short_signature() == selector)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "selector 0x{} does not match any function",
+                hex::encode(selector)
+            )
+        })?;
+    let tokens = function.decode_input(args)?;
+
+    let mut description = function.signature();
+    for (param, token) in function.inputs.iter().zip(&tokens) {
+        let name = if param.name.is_empty() {
+            "_"
+        } else {
+            &param.name
+        };
+        description.push_str(&format!("\n  {}: {} = ", name, param.kind));
+        describe_token(token, 2, &mut description);
+    }
+    Ok(description)
+}
+
+fn describe_token(token: &Token, depth: usize, description: &mut String) {
+    match token {
+        Token::Bytes(bytes) => match peek_fhe_metadata(&bytes.clone().into()) {
+            Ok(metadata) => description.push_str(&format!(
+                "ciphertext of {} bytes ({}, params {})",
+                bytes.len(),
+                metadata.data_type.name,
+                hex::encode(&metadata.params_hash[..4])
+            )),
+            Err(_) => {
+                description.push_str(&format!("{} bytes 0x{}", bytes.len(), hex::encode(bytes)))
+            }
+        },
+        Token::Array(tokens) | Token::FixedArray(tokens) | Token::Tuple(tokens) => {
+            description.push('[');
+            for (i, token) in tokens.iter().enumerate() {
+                description.push_str(&format!("\n{}{}: ", "  ".repeat(depth + 1), i));
+                describe_token(token, depth + 1, description);
+            }
+            if !tokens.is_empty() {
+                description.push_str(&format!("\n{}", "  ".repeat(depth)));
+            }
+            description.push(']');
+        }
+        Token::Address(address) => description.push_str(&format!("{:?}", address)),
+        Token::Uint(value) => description.push_str(&value.to_string()),
+        token => description.push_str(&token.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        middleware::gas_oracle,
+        providers::{HttpClientError, JsonRpcError, ProviderError},
+        signers::Signer,
+        types::{transaction::eip2930::AccessListItem, TransactionRequest, H256, U256},
+    };
+
+    use super::*;
+    use crate::testing::{Node, NodeOptions, ALICE, BOB};
+
+    /// A gas oracle which always suggests the same gas price.
+    #[derive(Debug)]
+    struct FixedOracle(U256);
+
+    #[async_trait::async_trait]
+    impl GasOracle for FixedOracle {
+        async fn fetch(&self) -> gas_oracle::Result<U256> {
+            Ok(self.0)
+        }
+
+        async fn estimate_eip1559_fees(&self) -> gas_oracle::Result<(U256, U256)> {
+            Ok((self.0, self.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn send_applies_gas_oracle() {
+        let node = Node::default();
+        let client = node.client(ALICE.clone());
+        let gas_price = U256::from(5_000_000_000u64);
+        let options = SendOptions {
+            gas_oracle: Some(Box::new(FixedOracle(gas_price))),
+            ..SendOptions::default()
+        };
+
+        let tx = TransactionRequest::new().to(BOB.address()).value(10000);
+        let receipt = send(&client, tx, &options).await.unwrap();
+
+        let tx = client
+            .get_transaction(receipt.transaction_hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tx.gas_price, Some(gas_price));
+    }
+
+    #[tokio::test]
+    async fn send_batch_stops_at_failure() {
+        let node = Node::default();
+        let client = node.client(ALICE.clone());
+        let transfer = |value: U256| TransactionRequest::new().to(BOB.address()).value(value);
+
+        let receipts = send_batch(&client, vec![transfer(1.into()), transfer(2.into())])
+            .await
+            .unwrap();
+        assert_eq!(receipts.len(), 2);
+        assert!(receipts[0].block_number < receipts[1].block_number);
+
+        let result = send_batch(
+            &client,
+            vec![transfer(1.into()), transfer(U256::MAX), transfer(1.into())],
+        )
+        .await;
+        assert!(matches!(result, Err(Error::Batch { index: 1, .. })));
+    }
+
+    #[tokio::test]
+    async fn send_with_progress_reports_each_step() {
+        let node = Node::default();
+        let client = node.client(ALICE.clone());
+        let tx = TransactionRequest::new().to(BOB.address()).value(1);
+
+        let mut statuses = Vec::new();
+        let receipt = send_with_progress(&client, tx, |status| statuses.push(status))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            statuses,
+            vec![
+                TxStatus::Submitted(receipt.transaction_hash),
+                TxStatus::Mined(Box::new(receipt)),
+                TxStatus::Confirmed,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn send_with_replacement_bumps_stuck_transactions() {
+        let node = Node {
+            poll_interval: Some(Duration::from_millis(50)),
+            ..Node::spawn()
+        };
+        let provider = node.provider();
+        provider
+            .request::<_, ()>("evm_setAutomine", [false])
+            .await
+            .unwrap();
+        let gas_price = provider.get_gas_price().await.unwrap();
+        let client = node.client(ALICE.clone());
+        let tx = TransactionRequest::new()
+            .to(BOB.address())
+            .value(1)
+            .gas_price(gas_price);
+
+        // Nothing is mined until after the first copy has timed out.
+        let miner = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(1200)).await;
+            provider
+                .request::<_, U256>("evm_mine", None::<()>)
+                .await
+                .unwrap();
+        });
+        let receipt = send_with_replacement(&client, tx, 20, 5, Duration::from_millis(500))
+            .await
+            .unwrap();
+        miner.await.unwrap();
+
+        assert!(receipt.effective_gas_price.unwrap() > gas_price);
+        assert_eq!(
+            client
+                .get_transaction_count(ALICE.address(), None)
+                .await
+                .unwrap(),
+            U256::one()
+        );
+    }
+
+    #[tokio::test]
+    async fn send_with_replacement_reports_time_spent() {
+        let node = Node::spawn_with(NodeOptions {
+            no_mining: true,
+            ..NodeOptions::default()
+        });
+        let client = node.client(ALICE.clone());
+        let tx = TransactionRequest::new().to(BOB.address()).value(1);
+
+        let timeout = Duration::from_millis(200);
+        let result = send_with_replacement(&client, tx, 20, 2, timeout).await;
+        assert!(matches!(result, Err(Error::Timeout(spent)) if spent >= timeout * 2));
+    }
+
+    #[tokio::test]
+    async fn cancel_tx_replaces_pending_transaction() {
+        let node = Node::spawn_with(NodeOptions {
+            no_mining: true,
+            ..NodeOptions::default()
+        });
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+        let gas_price = provider.get_gas_price().await.unwrap();
+        let tx = TransactionRequest::new()
+            .to(BOB.address())
+            .value(1000)
+            .gas_price(gas_price);
+        let original = client.send_transaction(tx, None).await.unwrap().tx_hash();
+
+        let cancel = cancel_tx(&client, U256::zero()).await.unwrap();
+        node.mine().await.unwrap();
+
+        let receipt = provider
+            .get_transaction_receipt(cancel)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.to, Some(ALICE.address()));
+        assert!(provider
+            .get_transaction_receipt(original)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn await_confirmations_detects_reorgs() {
+        let node = Node::default();
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+        let snapshot: U256 = provider.request("evm_snapshot", None::<()>).await.unwrap();
+
+        let tx = TransactionRequest::new().to(BOB.address()).value(1);
+        let receipt = send(&client, tx, &SendOptions::default()).await.unwrap();
+        node.mine_until(3).await.unwrap();
+        let confirmed = await_confirmations(&client, &receipt, 3).await.unwrap();
+        assert_eq!(confirmed.block_hash, receipt.block_hash);
+
+        // Roll the chain back to before the transaction was mined.
+        let reverted: bool = provider.request("evm_revert", [snapshot]).await.unwrap();
+        assert!(reverted);
+        assert!(matches!(
+            await_confirmations(&client, &receipt, 3).await,
+            Err(Error::Reorged(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn summarizes_account() {
+        let node = Node::default();
+        let client = node.client(ALICE.clone());
+        let initial = account_summary(&client).await.unwrap();
+        assert_eq!(initial.address, ALICE.address());
+        assert_eq!(initial.nonce, U256::zero());
+        assert_eq!(initial.chain_id, U256::from(node.chain_id));
+
+        let tx = TransactionRequest::new().to(BOB.address()).value(10000);
+        let receipt = send(&client, tx, &SendOptions::default()).await.unwrap();
+        let fee = receipt.gas_used.unwrap() * receipt.effective_gas_price.unwrap();
+
+        let summary = account_summary(&client).await.unwrap();
+        assert_eq!(summary.nonce, U256::one());
+        assert_eq!(summary.balance, initial.balance - fee - 10000);
+
+        let provider = node.provider();
+        assert!(account_summary(&provider).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dry_run_never_submits() {
+        let node = Node::default();
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+        let options = SendOptions::default();
+        let balance = provider.get_balance(BOB.address(), None).await.unwrap();
+
+        let tx = TransactionRequest::new().to(BOB.address()).value(10000);
+        let DryRun { tx, output } = dry_run(&client, tx, &options).await.unwrap();
+        assert_eq!(tx.from(), Some(&ALICE.address()));
+        assert_eq!(tx.to_addr(), Some(&BOB.address()));
+        assert_eq!(tx.gas(), Some(&U256::from(TRANSFER_GAS)));
+        assert!(output.is_empty());
+
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::zero());
+        assert_eq!(
+            provider.get_balance(BOB.address(), None).await.unwrap(),
+            balance
+        );
+
+        // Runtime code which always reverts.
+        let reverter = Address::repeat_byte(0x48);
+        provider
+            .request::<_, ()>(
+                "anvil_setCode",
+                (reverter, Bytes::from(vec![0x60, 0x00, 0x60, 0x00, 0xfd])),
+            )
+            .await
+            .unwrap();
+        let tx = TransactionRequest::new().to(reverter);
+        assert!(dry_run(&client, tx, &options).await.is_err());
+
+        // Runtime code which returns 42 as a word.
+        let returner = Address::repeat_byte(0x49);
+        provider
+            .request::<_, ()>(
+                "anvil_setCode",
+                (
+                    returner,
+                    Bytes::from(vec![
+                        0x60, 0x2a, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+                    ]),
+                ),
+            )
+            .await
+            .unwrap();
+        let tx = TransactionRequest::new().to(returner);
+        let output = dry_run(&client, tx, &options).await.unwrap().output;
+        assert_eq!(U256::from_big_endian(&output), U256::from(42));
+
+        // The transaction is prepared like `send` would, e.g. with the configured access list.
+        let access_list = AccessList(vec![AccessListItem {
+            address: returner,
+            storage_keys: vec![H256::zero()],
+        }]);
+        let options = SendOptions {
+            access_list: Some(access_list.clone()),
+            ..SendOptions::default()
+        };
+        let tx = TransactionRequest::new().to(returner);
+        let previewed = dry_run(&client, tx, &options).await.unwrap().tx;
+        assert!(matches!(previewed, TypedTransaction::Eip2930(_)));
+        assert_eq!(previewed.access_list(), Some(&access_list));
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::zero());
+    }
+
+    #[tokio::test]
+    async fn send_attaches_access_lists() {
+        let node = Node::default();
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+
+        // Runtime code which reads storage slot 0 of another contract's address.
+        let reader = Address::repeat_byte(0x47);
+        provider
+            .request::<_, ()>(
+                "anvil_setCode",
+                (reader, Bytes::from(vec![0x60, 0x00, 0x54, 0x00])),
+            )
+            .await
+            .unwrap();
+
+        let access_list = AccessList(vec![AccessListItem {
+            address: reader,
+            storage_keys: vec![H256::zero()],
+        }]);
+        let options = SendOptions {
+            access_list: Some(access_list.clone()),
+            ..SendOptions::default()
+        };
+        let receipt = send(&client, TransactionRequest::new().to(reader), &options)
+            .await
+            .unwrap();
+        let tx = provider
+            .get_transaction(receipt.transaction_hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tx.transaction_type, Some(U64::one()));
+        assert_eq!(tx.access_list, Some(access_list));
+
+        let options = SendOptions {
+            generate_access_list: true,
+            ..SendOptions::default()
+        };
+        let receipt = send(&client, TransactionRequest::new().to(reader), &options)
+            .await
+            .unwrap();
+        let tx = provider
+            .get_transaction(receipt.transaction_hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tx.transaction_type, Some(U64::one()));
+        assert!(tx.access_list.is_some());
+    }
+
+    #[tokio::test]
+    async fn recovers_revert_reason_of_mined_transaction() {
+        let node = Node::default();
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+
+        // Runtime code which reverts with `Error("nope")`, copied from the code after it.
+        let reason = abi::encode(&[Token::String("nope".to_owned())]);
+        let mut code = vec![
+            0x60, 0x64, 0x60, 0x0c, 0x60, 0x00, 0x39, 0x60, 0x64, 0x60, 0x00, 0xfd,
+        ];
+        code.extend_from_slice(&ERROR_SELECTOR);
+        code.extend_from_slice(&reason);
+        let reverter = Address::repeat_byte(0x46);
+        provider
+            .request::<_, ()>("anvil_setCode", (reverter, Bytes::from(code)))
+            .await
+            .unwrap();
+
+        let failing = TransactionRequest::new().to(reverter).gas(100_000);
+        let receipt = send(&client, failing, &SendOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(receipt.status, Some(U64::zero()));
+        assert_eq!(
+            revert_reason_from_receipt(&provider, &receipt)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("nope")
+        );
+
+        let transfer = TransactionRequest::new().to(BOB.address()).value(1);
+        let receipt = send(&client, transfer, &SendOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            revert_reason_from_receipt(&provider, &receipt)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[derive(Debug, PartialEq, EthEvent)]
+    struct Deposited {
+        amount: U256,
+    }
+
+    #[tokio::test]
+    async fn send_and_get_event_decodes_emitted_event() {
+        let node = Node::default();
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+
+        // Runtime code which emits `Deposited(42)`.
+        let mut code = vec![0x60, 0x2a, 0x60, 0x00, 0x52, 0x7f];
+        code.extend_from_slice(Deposited::signature().as_bytes());
+        code.extend_from_slice(&[0x60, 0x20, 0x60, 0x00, 0xa1, 0x00]);
+        let emitter = Address::repeat_byte(0x45);
+        provider
+            .request::<_, ()>("anvil_setCode", (emitter, Bytes::from(code)))
+            .await
+            .unwrap();
+
+        let event: Deposited = send_and_get_event(&client, TransactionRequest::new().to(emitter))
+            .await
+            .unwrap();
+        assert_eq!(event.amount, U256::from(42));
+
+        let silent = TransactionRequest::new().to(BOB.address()).value(1);
+        assert!(send_and_get_event::<Deposited, _>(&client, silent)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn estimates_workflow_cost() {
+        let node = Node::default();
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+        let gas_price = provider.get_gas_price().await.unwrap();
+        let transfer: TypedTransaction = TransactionRequest::new()
+            .from(ALICE.address())
+            .to(BOB.address())
+            .value(1)
+            .into();
+        let priced: TypedTransaction = TransactionRequest::new()
+            .from(ALICE.address())
+            .to(BOB.address())
+            .value(1)
+            .gas_price(gas_price * 2)
+            .into();
+
+        let cost = estimate_workflow_cost(&client, [transfer.clone(), priced])
+            .await
+            .unwrap();
+        assert_eq!(cost.total, gas_price * TRANSFER_GAS * 3);
+        assert!(cost.failed_steps().is_empty());
+
+        // Runtime code which always reverts with the data 0xdeadbeef.
+        let reverter = Address::repeat_byte(0x44);
+        let code = Bytes::from(vec![
+            0x63, 0xde, 0xad, 0xbe, 0xef, 0x60, 0x00, 0x52, 0x60, 0x04, 0x60, 0x1c, 0xfd,
+        ]);
+        provider
+            .request::<_, ()>("anvil_setCode", (reverter, code))
+            .await
+            .unwrap();
+        let reverting: TypedTransaction = TransactionRequest::new()
+            .from(ALICE.address())
+            .to(reverter)
+            .into();
+
+        // Every failing call is flagged, and the others still count towards the total.
+        let calls = [transfer.clone(), reverting.clone(), transfer, reverting];
+        let cost = estimate_workflow_cost(&client, calls).await.unwrap();
+        assert_eq!(cost.failed_steps(), [1, 3]);
+        assert!(matches!(cost.steps[1], Err(Error::Revert { .. })));
+        assert_eq!(cost.total, gas_price * TRANSFER_GAS * 2);
+    }
+
+    #[tokio::test]
+    async fn lists_transactions_from_address() {
+        let node = Node::default();
+        let alice = node.client(ALICE.clone());
+        let bob = node.client(BOB.clone());
+        let options = SendOptions::default();
+        let mut sent = Vec::new();
+        for client in [&alice, &bob, &alice] {
+            let tx = TransactionRequest::new()
+                .to(Address::repeat_byte(1))
+                .value(1);
+            sent.push(send(client, tx, &options).await.unwrap().transaction_hash);
+        }
+        let latest = alice.get_block_number().await.unwrap().as_u64();
+
+        let transactions = transactions_from(&alice, ALICE.address(), 0, latest)
+            .await
+            .unwrap();
+        let hashes = transactions.iter().map(|tx| tx.hash).collect::<Vec<_>>();
+        assert_eq!(hashes, [sent[0], sent[2]]);
+
+        assert!(
+            transactions_from(&alice, ALICE.address(), 0, MAX_SCAN_BLOCKS)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn watch_address_yields_matching_transactions() {
+        let node = Node {
+            poll_interval: Some(Duration::from_millis(10)),
+            ..Node::spawn()
+        };
+        let provider = node.provider();
+        let client = node.client(ALICE.clone());
+        let transactions = watch_address(&provider, BOB.address()).await.unwrap();
+        futures_util::pin_mut!(transactions);
+
+        let unrelated = TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .value(1);
+        send(&client, unrelated, &SendOptions::default())
+            .await
+            .unwrap();
+        let to_bob = TransactionRequest::new().to(BOB.address()).value(1);
+        let receipt = send(&client, to_bob, &SendOptions::default())
+            .await
+            .unwrap();
+
+        let tx = tokio::time::timeout(Duration::from_secs(5), transactions.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(tx.hash, receipt.transaction_hash);
+    }
+
+    #[tokio::test]
+    async fn rotate_wallet_moves_entire_balance() {
+        let node = Node::default();
+        let provider = node.provider();
+        let new = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let balance = provider.get_balance(BOB.address(), None).await.unwrap();
+
+        let receipt = rotate_wallet(&provider, &BOB, &new).await.unwrap();
+
+        let fee = receipt.gas_used.unwrap() * receipt.effective_gas_price.unwrap();
+        assert_eq!(
+            provider.get_balance(BOB.address(), None).await.unwrap(),
+            U256::zero()
+        );
+        assert_eq!(
+            provider.get_balance(new.address(), None).await.unwrap(),
+            balance - fee
+        );
+    }
+
+    fn rpc_error(message: &str) -> ProviderError {
+        rpc_error_with_data(message, None)
+    }
+
+    fn rpc_error_with_data(message: &str, data: Option<serde_json::Value>) -> ProviderError {
+        ProviderError::JsonRpcClientError(Box::new(HttpClientError::JsonRpcError(JsonRpcError {
+            code: -32000,
+            message: message.to_owned(),
+            data,
+        })))
+    }
+
+    #[test]
+    fn decodes_custom_errors() {
+        let abi = abi::parse_abi(&[
+            "error InsufficientEncryptedBalance(uint256 needed)",
+            "error Unauthorized()",
+        ])
+        .unwrap();
+        let error = &abi.errors_by_name("InsufficientEncryptedBalance").unwrap()[0];
+        let data = Bytes::from(error.encode(&[Token::Uint(U256::from(42))]).unwrap());
+
+        let decoded = decode_custom_error(&data, &abi).unwrap();
+        assert_eq!(decoded.name, "InsufficientEncryptedBalance");
+        assert_eq!(
+            decoded.params,
+            vec![("needed".to_owned(), Token::Uint(U256::from(42)))]
+        );
+
+        assert!(decode_custom_error(&Bytes::from(ERROR_SELECTOR.to_vec()), &abi).is_none());
+        assert!(decode_custom_error(&Bytes::from(vec![1, 2]), &abi).is_none());
+    }
+
+    #[test]
+    fn decodes_revert_reasons() {
+        let error = [
+            &ERROR_SELECTOR[..],
+            &abi::encode(&[Token::String("insufficient balance".to_owned())]),
+        ]
+        .concat();
+        assert_eq!(
+            decode_revert_reason(&error).as_deref(),
+            Some("insufficient balance")
+        );
+
+        let panic = [
+            &PANIC_SELECTOR[..],
+            &abi::encode(&[Token::Uint(0x11.into())]),
+        ]
+        .concat();
+        assert_eq!(
+            decode_revert_reason(&panic).as_deref(),
+            Some("panic code 0x11")
+        );
+
+        assert_eq!(decode_revert_reason(&[0xde, 0xad, 0xbe, 0xef]), None);
+
+        let data = Bytes::from(error);
+        match middleware_error(rpc_error_with_data(
+            "execution reverted",
+            Some(serde_json::json!(data)),
+        )) {
+            Error::Revert { reason, data: d } => {
+                assert_eq!(reason.as_deref(), Some("insufficient balance"));
+                assert_eq!(d, data);
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn classifies_rpc_errors() {
+        assert!(matches!(
+            middleware_error(rpc_error("already known")),
+            Error::AlreadyKnown
+        ));
+        assert!(matches!(
+            middleware_error(rpc_error("nonce too low: next nonce 3, tx nonce 2")),
+            Error::Nonce(_)
+        ));
+        assert!(matches!(
+            middleware_error(rpc_error("insufficient funds for gas * price + value")),
+            Error::Other(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn describes_fhe_calldata() {
+        use crate::{tests::runtime, AsBytes, Unsigned256};
+
+        let runtime = runtime();
+        let (public_key, _) = runtime.generate_keys().unwrap();
+        let ciphertext = runtime
+            .encrypt(Unsigned256::from(3), &public_key)
+            .unwrap()
+            .as_bytes()
+            .unwrap();
+        let abi =
+            abi::parse_abi(&["function deposit(address to, bytes amount, bytes memo)"]).unwrap();
+        let input = abi
+            .function("deposit")
+            .unwrap()
+            .encode_input(&[
+                Token::Address(BOB.address()),
+                Token::Bytes(ciphertext.to_vec()),
+                Token::Bytes(b"hi".to_vec()),
+            ])
+            .unwrap();
+
+        let node = Node::default();
+        let client = node.client(ALICE.clone());
+        let tx = TransactionRequest::new().to(BOB.address()).data(input);
+        let receipt = send(&client, tx, &SendOptions::default()).await.unwrap();
+
+        let description = describe_tx_calldata(&client, receipt.transaction_hash, &abi)
+            .await
+            .unwrap();
+        let lines = description.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "deposit(address,bytes,bytes)");
+        assert_eq!(lines[1], format!("  to: address = {:?}", BOB.address()));
+        assert!(lines[2].starts_with(&format!(
+            "  amount: bytes = ciphertext of {} bytes (",
+            ciphertext.len()
+        )));
+        assert_eq!(lines[3], "  memo: bytes = 2 bytes 0x6869");
+
+        let other = abi::parse_abi(&["function withdraw(uint256 amount)"]).unwrap();
+        assert!(
+            describe_tx_calldata(&client, receipt.transaction_hash, &other)
+                .await
+                .is_err()
+        );
+    }
+}